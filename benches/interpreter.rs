@@ -0,0 +1,74 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use teolang::program::parser::Ast;
+use teolang::program::{Engine, Program};
+
+fn run(ast: &Ast, engine: Engine) {
+    let mut program = Program::builder()
+        .commands(ast.clone())
+        .engine(engine)
+        .build()
+        .unwrap();
+    program.run().unwrap();
+}
+
+fn benchmark_interpreter(c: &mut Criterion) {
+    let programs = [
+        (
+            "loop",
+            r#"
+            total = 0;
+            for i <- 0..2000 {
+                if (i > 1000) {
+                    total += i * 2;
+                }
+                total -= 1;
+            }
+            "#,
+        ),
+        (
+            "recursion",
+            r#"
+            def fib(n: Number) {
+                if (n < 2) {
+                    return(n);
+                }
+                return(fib(n - 1) + fib(n - 2));
+            }
+            x = fib(15);
+            "#,
+        ),
+        (
+            "array",
+            r#"
+            x = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+            for i <- 0..50 {
+                for j <- 0..10 {
+                    x[j] += i;
+                }
+            }
+            "#,
+        ),
+        (
+            "array_read",
+            r#"
+            x = range(1000);
+            total = 0;
+            for i <- 0..1000 {
+                total += x[i];
+            }
+            "#,
+        ),
+    ];
+    for (name, source) in programs {
+        let ast = Ast::parse_code(source).unwrap();
+        for (engine_name, engine) in [("tree", Engine::TreeWalker), ("bytecode", Engine::Bytecode)]
+        {
+            c.bench_function(&format!("{}/{}", name, engine_name), |b| {
+                b.iter(|| run(black_box(&ast), engine))
+            });
+        }
+    }
+}
+
+criterion_group!(benches, benchmark_interpreter);
+criterion_main!(benches);
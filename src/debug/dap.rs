@@ -0,0 +1,385 @@
+//! `teo debug --dap`: the debugger of `teo debug` for editors, over the Debug Adapter
+//! Protocol on stdin and stdout.
+//!
+//! The editor launches a program with `{"program": path, "args": [...], "stopOnEntry":
+//! bool}`. What the program prints comes back as `output` events, and `input()` reads
+//! nothing since stdin carries the protocol. Only the function being run shows its
+//! variables.
+
+use super::{frames, sorted};
+use crate::lsp::{read_message, write_message};
+use crate::{exit_code, Failure};
+use anyhow::{anyhow, Context, Result};
+use serde_json::{json, Value};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::rc::Rc;
+use teolang::program::debug::{Debugger, Mode, Pause, Reason, Stepper, Stopped};
+use teolang::program::parser::Ast;
+use teolang::program::{Data, ProgramBuilder, ReturnType};
+
+/// Programs run on a single thread
+const THREAD: u64 = 1;
+
+/// The only variables reference: those of the function being run
+const VARIABLES: u64 = 1;
+
+/// Serves one editor until it disconnects or closes stdin
+pub fn run(builder: ProgramBuilder) -> Result<()> {
+    let client = Rc::new(Client {
+        input: RefCell::new(io::stdin().lock()),
+        output: RefCell::new(io::stdout()),
+        seq: Cell::new(0),
+        disconnected: Cell::new(false),
+    });
+    let mut builder = Some(builder);
+    let mut launch = None;
+    let mut breakpoints = BTreeSet::new();
+    while let Some(request) = client.receive()? {
+        match request["command"].as_str().unwrap_or_default() {
+            "initialize" => {
+                client.respond(
+                    &request,
+                    json!({
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsEvaluateForHovers": true,
+                    }),
+                )?;
+                client.event("initialized", json!({}))?;
+            }
+            "launch" => match Launch::read(&request["arguments"]) {
+                Ok(read) => {
+                    launch = Some(read);
+                    client.respond(&request, Value::Null)?;
+                }
+                Err(e) => client.fail(&request, format!("{:#}", e))?,
+            },
+            "setBreakpoints" => breakpoints = client.set_breakpoints(&request)?,
+            "configurationDone" => {
+                client.respond(&request, Value::Null)?;
+                // Breakpoints are all set by now, so the program can start
+                if let (Some(launch), Some(builder)) = (launch.take(), builder.take()) {
+                    launch.run(builder, breakpoints.clone(), &client)?;
+                }
+            }
+            "threads" => client.respond(&request, threads())?,
+            "disconnect" => {
+                client.respond(&request, Value::Null)?;
+                break;
+            }
+            command => client.fail(&request, format!("Unknown command {}", command))?,
+        }
+        if client.disconnected.get() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn threads() -> Value {
+    json!({ "threads": [{ "id": THREAD, "name": "main" }] })
+}
+
+/// The editor on the other end
+struct Client {
+    input: RefCell<io::StdinLock<'static>>,
+    output: RefCell<io::Stdout>,
+    /// Number of the last message sent
+    seq: Cell<u64>,
+    /// Set once the editor disconnected while the program ran
+    disconnected: Cell<bool>,
+}
+
+impl Client {
+    fn receive(&self) -> Result<Option<Value>> {
+        read_message(&mut *self.input.borrow_mut())
+    }
+
+    fn send(&self, mut message: Value) -> Result<()> {
+        self.seq.set(self.seq.get() + 1);
+        message["seq"] = self.seq.get().into();
+        write_message(&mut *self.output.borrow_mut(), &message)
+    }
+
+    fn respond(&self, request: &Value, body: Value) -> Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "success": true,
+            "command": request["command"],
+            "body": body,
+        }))
+    }
+
+    fn fail(&self, request: &Value, message: impl fmt::Display) -> Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "success": false,
+            "command": request["command"],
+            "message": message.to_string(),
+        }))
+    }
+
+    fn event(&self, event: &str, body: Value) -> Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn output(&self, category: &str, output: impl fmt::Display) -> Result<()> {
+        self.event(
+            "output",
+            json!({ "category": category, "output": output.to_string() }),
+        )
+    }
+
+    /// Answers `setBreakpoints` and returns the lines to stop at
+    fn set_breakpoints(&self, request: &Value) -> Result<BTreeSet<usize>> {
+        let lines: BTreeSet<usize> = request["arguments"]["breakpoints"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|breakpoint| breakpoint["line"].as_u64())
+            .map(|line| line as usize)
+            .collect();
+        let breakpoints: Vec<Value> = lines
+            .iter()
+            .map(|line| json!({ "verified": true, "line": line }))
+            .collect();
+        self.respond(request, json!({ "breakpoints": breakpoints }))?;
+        Ok(lines)
+    }
+}
+
+/// Sends what the program prints as `output` events
+struct Output(Rc<Client>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .output("stdout", String::from_utf8_lossy(buf))
+            .map_err(io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A program the editor asked to run
+struct Launch {
+    path: String,
+    ast: Ast,
+    args: Vec<String>,
+    stop_on_entry: bool,
+}
+
+impl Launch {
+    fn read(arguments: &Value) -> Result<Launch> {
+        let path = arguments["program"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Say which `program` to run"))?;
+        let code = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read file from {}", path))?;
+        let ast = Ast::parse_code(&code)
+            .with_context(|| format!("Failed to parse file from {}", path))?;
+        let args = arguments["args"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|arg| arg.as_str().map(str::to_string))
+            .collect();
+        Ok(Launch {
+            path: path.to_string(),
+            ast,
+            args,
+            stop_on_entry: arguments["stopOnEntry"].as_bool().unwrap_or(false),
+        })
+    }
+
+    /// Runs the program to its end, then tells the editor how it exited
+    fn run(
+        self,
+        builder: ProgramBuilder,
+        breakpoints: BTreeSet<usize>,
+        client: &Rc<Client>,
+    ) -> Result<()> {
+        let mut stepper = Stepper::new(if self.stop_on_entry {
+            Mode::Step
+        } else {
+            Mode::Continue
+        });
+        stepper.breakpoints = breakpoints;
+        let adapter = Adapter {
+            client: client.clone(),
+            stepper,
+            path: self.path,
+        };
+        let result = builder
+            .commands(self.ast)
+            .args(self.args)
+            .input(Rc::new(RefCell::new(io::empty())))
+            .output(Rc::new(RefCell::new(Output(client.clone()))))
+            .debugger(adapter)
+            .build()
+            .and_then(|mut program| program.run());
+        let code = match result {
+            Ok(returned) => {
+                if let ReturnType::Ok(value @ (Data::String(_) | Data::Array(_))) = &returned {
+                    client.output("stdout", format!("{}\n", value.as_string()))?;
+                }
+                exit_code(&returned)
+            }
+            Err(_) if client.disconnected.get() => return Ok(()),
+            Err(e) => {
+                client.output("stderr", format!("error: {:#}\n", e))?;
+                Failure::from(e).code
+            }
+        };
+        client.event("exited", json!({ "exitCode": code }))?;
+        client.event("terminated", json!({}))
+    }
+}
+
+/// The debugger of a launched program, answering the editor while it is paused
+struct Adapter {
+    client: Rc<Client>,
+    stepper: Stepper,
+    path: String,
+}
+
+impl Adapter {
+    /// Answers a request made while paused. Returns how to go on for those that resume the
+    /// program.
+    fn request(&mut self, request: &Value, pause: &Pause<'_>) -> Result<Option<Mode>> {
+        let arguments = &request["arguments"];
+        let body = match request["command"].as_str().unwrap_or_default() {
+            "continue" => {
+                self.client
+                    .respond(request, json!({ "allThreadsContinued": true }))?;
+                return Ok(Some(Mode::Continue));
+            }
+            "next" => Some(Mode::Next),
+            "stepIn" => Some(Mode::Step),
+            "stepOut" => Some(Mode::Out),
+            "threads" => return self.client.respond(request, threads()).map(|_| None),
+            "setBreakpoints" => {
+                self.stepper.breakpoints = self.client.set_breakpoints(request)?;
+                return Ok(None);
+            }
+            "stackTrace" => {
+                let name = self.path.rsplit(['/', '\\']).next().unwrap_or_default();
+                let frames: Vec<Value> = frames(pause)
+                    .into_iter()
+                    .enumerate()
+                    .map(|(id, (function, line))| {
+                        json!({
+                            "id": id,
+                            "name": function.unwrap_or("top level"),
+                            "line": line,
+                            "column": 1,
+                            "source": { "name": name, "path": self.path },
+                        })
+                    })
+                    .collect();
+                let total = frames.len();
+                return self
+                    .client
+                    .respond(
+                        request,
+                        json!({ "stackFrames": frames, "totalFrames": total }),
+                    )
+                    .map(|_| None);
+            }
+            "scopes" => {
+                let scopes = match arguments["frameId"].as_u64() {
+                    Some(0) => json!([{
+                        "name": "Variables",
+                        "variablesReference": VARIABLES,
+                        "expensive": false,
+                    }]),
+                    _ => json!([]),
+                };
+                return self
+                    .client
+                    .respond(request, json!({ "scopes": scopes }))
+                    .map(|_| None);
+            }
+            "variables" => {
+                let variables: Vec<Value> = match arguments["variablesReference"].as_u64() {
+                    Some(VARIABLES) => sorted(pause.variables)
+                        .into_iter()
+                        .map(|(name, value)| {
+                            json!({
+                                "name": name,
+                                "value": value.as_code(),
+                                "type": value.data_type().to_string(),
+                                "variablesReference": 0,
+                            })
+                        })
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                return self
+                    .client
+                    .respond(request, json!({ "variables": variables }))
+                    .map(|_| None);
+            }
+            "evaluate" => {
+                let expression = arguments["expression"].as_str().unwrap_or_default().trim();
+                match pause.variables.get(expression) {
+                    Some(value) => self.client.respond(
+                        request,
+                        json!({ "result": value.as_code(), "variablesReference": 0 }),
+                    )?,
+                    None => self.client.fail(
+                        request,
+                        format!("There is no variable `{}` here", expression),
+                    )?,
+                }
+                return Ok(None);
+            }
+            "disconnect" => {
+                self.client.respond(request, Value::Null)?;
+                self.client.disconnected.set(true);
+                return Err(Stopped.into());
+            }
+            command => {
+                self.client
+                    .fail(request, format!("Unknown command {}", command))?;
+                return Ok(None);
+            }
+        };
+        self.client.respond(request, Value::Null)?;
+        Ok(body)
+    }
+}
+
+impl Debugger for Adapter {
+    fn pause(&mut self, pause: &Pause<'_>) -> Result<()> {
+        let Some(reason) = self.stepper.stop(pause) else {
+            return Ok(());
+        };
+        let reason = match reason {
+            Reason::Entry => "entry",
+            Reason::Step => "step",
+            Reason::Breakpoint => "breakpoint",
+        };
+        self.client.event(
+            "stopped",
+            json!({ "reason": reason, "threadId": THREAD, "allThreadsStopped": true }),
+        )?;
+        while let Some(request) = self.client.receive()? {
+            if let Some(mode) = self.request(&request, pause)? {
+                self.stepper.mode = mode;
+                return Ok(());
+            }
+        }
+        // The editor went away
+        self.client.disconnected.set(true);
+        Err(Stopped.into())
+    }
+}
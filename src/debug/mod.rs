@@ -0,0 +1,244 @@
+//! `teo debug`: runs a program one statement at a time, stopping at breakpoints to show where
+//! it is and what its variables hold. Commands are read from stdin and everything but the
+//! program's own output goes to stderr.
+
+use anyhow::Result;
+use std::collections::HashMap;
+use std::io::{self, BufRead, IsTerminal, Write};
+use teolang::program::debug::{Debugger, Frame, Mode, Pause, Reason, Stepper, Stopped};
+use teolang::program::Data;
+use teolang::util::shell::Shell;
+
+pub mod dap;
+
+const HELP: &str = "\
+step, s          run to the next line, going into the functions it calls
+next, n          run to the next line of this function
+out, o           run until this function returns
+continue, c      run until a breakpoint
+break N, b N     stop at line N (without N: show the breakpoints)
+delete N, d N    stop stopping at line N
+vars, v          show the variables of this function
+print NAME, p    show one variable
+stack, bt        show the functions being run
+list, l          show the code around this line
+quit, q          stop the program
+help, h          show this message
+Pressing enter runs the last command again.";
+
+/// Function of each frame ([`None`] for the top level) with the line it is at, innermost
+/// first
+fn frames<'a>(pause: &Pause<'a>) -> Vec<(Option<&'a str>, usize)> {
+    let mut frames = Vec::new();
+    let mut line = pause.line;
+    for Frame {
+        function,
+        line: call,
+    } in pause.stack.iter().rev()
+    {
+        frames.push((Some(function.as_str()), line));
+        line = *call;
+    }
+    frames.push((None, line));
+    frames
+}
+
+fn sorted(variables: &HashMap<String, Data>) -> Vec<(&String, &Data)> {
+    let mut variables: Vec<_> = variables.iter().collect();
+    variables.sort_by(|a, b| a.0.cmp(b.0));
+    variables
+}
+
+/// The debugger of `teo debug`. It reads its commands from stdin a line at a time, like the
+/// program's `input()`, so each reads the next line when it needs one: a program that reads
+/// input while being debugged gets the lines typed at that point, not commands.
+pub struct Terminal {
+    /// Lines of the program's code
+    code: Vec<String>,
+    stepper: Stepper,
+    shell: Shell,
+    /// Where the commands come from instead of stdin, see [`Terminal::commands`]
+    commands: Option<Box<dyn BufRead>>,
+    interactive: bool,
+    last_command: String,
+}
+
+impl Terminal {
+    pub fn new(code: &str) -> Terminal {
+        Terminal {
+            code: code.lines().map(str::to_string).collect(),
+            stepper: Stepper::new(Mode::Step),
+            shell: Shell::new(),
+            commands: None,
+            interactive: io::stdin().is_terminal(),
+            last_command: String::new(),
+        }
+    }
+
+    /// Reads the commands from `commands`, leaving stdin to the program
+    pub fn commands(mut self, commands: impl BufRead + 'static) -> Terminal {
+        self.commands = Some(Box::new(commands));
+        self.interactive = false;
+        self
+    }
+
+    fn code(&self, line: usize) -> &str {
+        line.checked_sub(1)
+            .and_then(|i| self.code.get(i))
+            .map_or("", |code| code.trim())
+    }
+
+    /// Reads a command, the last one again for an empty line. [`None`] once the commands end.
+    fn read_command(&mut self) -> Result<Option<String>> {
+        if self.interactive {
+            eprint!("(teo) ");
+            io::stderr().flush()?;
+        }
+        let mut command = String::new();
+        let read = match &mut self.commands {
+            Some(commands) => commands.read_line(&mut command)?,
+            None => io::stdin().lock().read_line(&mut command)?,
+        };
+        if read == 0 {
+            return Ok(None);
+        }
+        let command = command.trim();
+        if !command.is_empty() {
+            self.last_command = command.to_string();
+        }
+        Ok(Some(self.last_command.clone()))
+    }
+
+    /// Runs a command that does not resume the program
+    fn command(&mut self, name: &str, argument: Option<&str>, pause: &Pause<'_>) -> Result<()> {
+        let line = || match argument.map(str::parse::<usize>) {
+            Some(Ok(line)) => Ok(line),
+            _ => Err(format!("`{}` needs a line number, like `{} 4`", name, name)),
+        };
+        match name {
+            "break" | "b" if argument.is_none() => {
+                if self.stepper.breakpoints.is_empty() {
+                    eprintln!("No breakpoints, add one with `break N`");
+                }
+                for line in &self.stepper.breakpoints {
+                    eprintln!("line {}: {}", line, self.code(*line));
+                }
+            }
+            "break" | "b" => match line() {
+                Ok(line) if line == 0 || line > self.code.len() => {
+                    self.shell.error(format!("There is no line {}", line))?
+                }
+                Ok(line) => {
+                    self.stepper.breakpoints.insert(line);
+                    eprintln!("Breakpoint at line {}: {}", line, self.code(line));
+                }
+                Err(e) => self.shell.error(e)?,
+            },
+            "delete" | "d" => match line() {
+                Ok(line) if self.stepper.breakpoints.remove(&line) => {
+                    eprintln!("Removed the breakpoint at line {}", line)
+                }
+                Ok(line) => self
+                    .shell
+                    .error(format!("There is no breakpoint at line {}", line))?,
+                Err(e) => self.shell.error(e)?,
+            },
+            "vars" | "v" => {
+                if pause.variables.is_empty() {
+                    eprintln!("No variables yet");
+                }
+                for (name, value) in sorted(pause.variables) {
+                    eprintln!("{} = {}", name, value.as_code());
+                }
+            }
+            "print" | "p" => match argument {
+                Some(name) => match pause.variables.get(name) {
+                    Some(value) => eprintln!("{} = {}", name, value.as_code()),
+                    None => self
+                        .shell
+                        .error(format!("There is no variable `{}` here", name))?,
+                },
+                None => self
+                    .shell
+                    .error("`print` needs a variable, like `print x`")?,
+            },
+            "stack" | "bt" => {
+                for (function, line) in frames(pause) {
+                    let function = match function {
+                        Some(function) => format!("`{}`", function),
+                        None => "the top level".to_string(),
+                    };
+                    eprintln!("in {} at line {}: {}", function, line, self.code(line));
+                }
+            }
+            "list" | "l" => {
+                let first = pause.line.saturating_sub(3).max(1);
+                for line in first..=(pause.line + 3).min(self.code.len()) {
+                    let marker = if line == pause.line { '>' } else { ' ' };
+                    let breakpoint = if self.stepper.breakpoints.contains(&line) {
+                        '*'
+                    } else {
+                        ' '
+                    };
+                    eprintln!(
+                        "{}{}{:>4}  {}",
+                        marker,
+                        breakpoint,
+                        line,
+                        self.code[line - 1]
+                    );
+                }
+            }
+            "help" | "h" => eprintln!("{}", HELP),
+            other => self
+                .shell
+                .error(format!("Unknown command `{}`, type `help` for help", other))?,
+        }
+        Ok(())
+    }
+}
+
+impl Debugger for Terminal {
+    fn pause(&mut self, pause: &Pause<'_>) -> Result<()> {
+        let Some(reason) = self.stepper.stop(pause) else {
+            return Ok(());
+        };
+        let status = match reason {
+            Reason::Entry => "Starting",
+            Reason::Step => "Paused",
+            Reason::Breakpoint => "Breakpoint",
+        };
+        self.shell.status_with_color(
+            status,
+            format!("at line {}: {}", pause.line, self.code(pause.line)),
+            termcolor::Color::Cyan,
+        )?;
+        if reason == Reason::Entry && self.interactive {
+            eprintln!("Type `help` to see what the debugger can do");
+        }
+        loop {
+            let Some(command) = self.read_command()? else {
+                // Without more commands, the program runs to its end
+                self.stepper.breakpoints.clear();
+                self.stepper.mode = Mode::Continue;
+                return Ok(());
+            };
+            let (name, argument) = match command.split_once(' ') {
+                Some((name, argument)) => (name, Some(argument.trim())),
+                None => (command.as_str(), None),
+            };
+            self.stepper.mode = match name {
+                "step" | "s" => Mode::Step,
+                "next" | "n" => Mode::Next,
+                "out" | "o" => Mode::Out,
+                "continue" | "c" => Mode::Continue,
+                "quit" | "q" => return Err(Stopped.into()),
+                _ => {
+                    self.command(name, argument, pause)?;
+                    continue;
+                }
+            };
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,377 @@
+//! `teo lsp`: a Language Server Protocol server over stdin and stdout, for editors.
+//!
+//! It keeps the text of every open document, and after each change sends the parse error or
+//! the diagnostics of [`check`] back. It also answers completion, hover, go to definition
+//! and formatting requests. Positions count characters rather than UTF-16 code units, which
+//! is the same for the code kids type.
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use teolang::program::check::{self, Severity, Typing};
+use teolang::program::format;
+use teolang::program::native::NativeRegistry;
+use teolang::program::parser::{self, Ast, Statement};
+
+/// JSON-RPC error code for requests the server does not know
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Words of the language, offered as completions
+const KEYWORDS: [&str; 7] = ["if", "for", "def", "step", "return", "true", "false"];
+
+/// Serves one client until it sends `exit` or closes stdin
+pub fn run(natives: NativeRegistry) -> Result<()> {
+    let mut server = Server {
+        natives,
+        documents: HashMap::new(),
+        output: io::stdout().lock(),
+    };
+    let mut input = io::stdin().lock();
+    while let Some(message) = read_message(&mut input)? {
+        if !server.handle(message)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// An open document
+struct Document {
+    text: String,
+    /// Syntax tree of the last version that parsed, to keep completing while the code is
+    /// being typed
+    ast: Option<Ast>,
+}
+
+struct Server<W: Write> {
+    natives: NativeRegistry,
+    documents: HashMap<String, Document>,
+    output: W,
+}
+
+impl<W: Write> Server<W> {
+    /// Handles a request or notification. Returns `false` once the client asks to exit.
+    fn handle(&mut self, message: Value) -> Result<bool> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let result = match method {
+            "initialize" => Some(json!({
+                "capabilities": {
+                    // The whole text is sent on every change
+                    "textDocumentSync": 1,
+                    "completionProvider": {},
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "documentFormattingProvider": true,
+                },
+                "serverInfo": { "name": "teo", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "shutdown" => Some(Value::Null),
+            "exit" => return Ok(false),
+            "textDocument/didOpen" => {
+                let document = &params["textDocument"];
+                self.update(uri(params), document["text"].as_str().unwrap_or_default())?;
+                None
+            }
+            "textDocument/didChange" => {
+                let text = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str())
+                    .unwrap_or_default();
+                self.update(uri(params), text)?;
+                None
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri(params));
+                None
+            }
+            "textDocument/completion" => Some(self.completion(&uri(params))),
+            "textDocument/hover" => Some(self.hover(&uri(params), &params["position"])),
+            "textDocument/definition" => Some(self.definition(&uri(params), &params["position"])),
+            "textDocument/formatting" => Some(self.formatting(&uri(params))),
+            _ => {
+                // Notifications (without an `id`) the server does not know are ignored
+                if message.get("id").is_some() {
+                    self.send(&json!({
+                        "jsonrpc": "2.0",
+                        "id": message["id"],
+                        "error": {
+                            "code": METHOD_NOT_FOUND,
+                            "message": format!("Unknown method {}", method),
+                        },
+                    }))?;
+                }
+                None
+            }
+        };
+        if let Some(result) = result {
+            self.send(&json!({ "jsonrpc": "2.0", "id": message["id"], "result": result }))?;
+        }
+        Ok(true)
+    }
+
+    fn send(&mut self, message: &Value) -> Result<()> {
+        write_message(&mut self.output, message)
+    }
+
+    /// Stores the new text of a document and sends its diagnostics
+    fn update(&mut self, uri: String, text: &str) -> Result<()> {
+        let mut diagnostics = Vec::new();
+        let ast = match Ast::parse_code(text) {
+            Ok(ast) => {
+                for diagnostic in check::check(&ast, &self.natives, Typing::Gradual) {
+                    let line = text.lines().nth(diagnostic.line - 1).unwrap_or_default();
+                    diagnostics.push(json!({
+                        "range": range(diagnostic.line - 1, 0, line.chars().count()),
+                        "severity": match diagnostic.severity {
+                            Severity::Error => 1,
+                            Severity::Warning => 2,
+                        },
+                        "source": "teo",
+                        "message": diagnostic.message,
+                    }));
+                }
+                Some(ast)
+            }
+            Err(e) => {
+                let (line, column) = parser::error_location(&e).unwrap_or((1, 1));
+                // The last line of a parse error says what was expected
+                let message = e.to_string();
+                let message = message.lines().last().unwrap_or_default().trim();
+                diagnostics.push(json!({
+                    "range": range(line - 1, column - 1, column - 1),
+                    "severity": 1,
+                    "source": "teo",
+                    "message": message.trim_start_matches("= "),
+                }));
+                self.documents.get(&uri).and_then(|d| d.ast.clone())
+            }
+        };
+        self.documents.insert(
+            uri.clone(),
+            Document {
+                text: text.to_string(),
+                ast,
+            },
+        );
+        self.send(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        }))
+    }
+
+    fn completion(&self, uri: &str) -> Value {
+        // Kinds of completion items, as numbered by the protocol
+        const FUNCTION: u8 = 3;
+        const VARIABLE: u8 = 6;
+        const KEYWORD: u8 = 14;
+        let mut items = Vec::new();
+        for native in self.natives.iter() {
+            items.push(json!({
+                "label": native.name(),
+                "kind": FUNCTION,
+                "detail": native.docs(),
+            }));
+        }
+        let names = self.names(uri);
+        for (id, (signature, _)) in &names.functions {
+            items.push(json!({ "label": id, "kind": FUNCTION, "detail": signature }));
+        }
+        for variable in &names.variables {
+            items.push(json!({ "label": variable, "kind": VARIABLE }));
+        }
+        for keyword in KEYWORDS {
+            items.push(json!({ "label": keyword, "kind": KEYWORD }));
+        }
+        Value::Array(items)
+    }
+
+    fn hover(&self, uri: &str, position: &Value) -> Value {
+        let Some(word) = self.word_at(uri, position) else {
+            return Value::Null;
+        };
+        let contents = if let Some(native) = self.natives.get(&word) {
+            native.docs().to_string()
+        } else if let Some((signature, _)) = self.names(uri).functions.get(&word) {
+            format!("```teo\n{}\n```", signature)
+        } else {
+            return Value::Null;
+        };
+        json!({ "contents": { "kind": "markdown", "value": contents } })
+    }
+
+    fn definition(&self, uri: &str, position: &Value) -> Value {
+        let Some(word) = self.word_at(uri, position) else {
+            return Value::Null;
+        };
+        let Some((_, line)) = self.names(uri).functions.get(&word).cloned() else {
+            return Value::Null;
+        };
+        let text = self.documents[uri]
+            .text
+            .lines()
+            .nth(line - 1)
+            .unwrap_or_default();
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        // The name right after `def`, not an earlier match like the `f` of `def` itself
+        let column = text
+            .match_indices(word.as_str())
+            .find(|(byte, _)| {
+                let before = &text[..*byte];
+                let keyword = before.trim_end();
+                before.ends_with(char::is_whitespace)
+                    && keyword.ends_with("def")
+                    && !keyword[..keyword.len() - 3].ends_with(is_word)
+                    && !text[byte + word.len()..].starts_with(is_word)
+            })
+            .map_or(0, |(byte, _)| text[..byte].chars().count());
+        json!({
+            "uri": uri,
+            "range": range(line - 1, column, column + word.chars().count()),
+        })
+    }
+
+    fn formatting(&self, uri: &str) -> Value {
+        let Some(document) = self.documents.get(uri) else {
+            return Value::Null;
+        };
+        // Code that does not parse is left alone
+        let Ok(ast) = Ast::parse_code(&document.text) else {
+            return Value::Null;
+        };
+        let lines = document.text.lines().count();
+        json!([{
+            "range": { "start": position(0, 0), "end": position(lines + 1, 0) },
+            "newText": format::format(&ast),
+        }])
+    }
+
+    fn names(&self, uri: &str) -> Names {
+        let mut names = Names::default();
+        if let Some(ast) = self.documents.get(uri).and_then(|d| d.ast.as_ref()) {
+            names.collect(ast);
+        }
+        names
+    }
+
+    /// Identifier under `position`
+    fn word_at(&self, uri: &str, position: &Value) -> Option<String> {
+        let document = self.documents.get(uri)?;
+        let line = document
+            .text
+            .lines()
+            .nth(position["line"].as_u64()? as usize)?;
+        let chars: Vec<char> = line.chars().collect();
+        let at = (position["character"].as_u64()? as usize).min(chars.len());
+        let is_word = |c: &char| c.is_alphanumeric() || *c == '_';
+        let start = chars[..at]
+            .iter()
+            .rposition(|c| !is_word(c))
+            .map_or(0, |i| i + 1);
+        let end = chars[at..]
+            .iter()
+            .position(|c| !is_word(c))
+            .map_or(chars.len(), |i| at + i);
+        (start < end).then(|| chars[start..end].iter().collect())
+    }
+}
+
+/// Functions and variables of a program
+#[derive(Default)]
+struct Names {
+    /// Signature and line of each function
+    functions: HashMap<String, (String, usize)>,
+    variables: Vec<String>,
+}
+
+impl Names {
+    fn collect(&mut self, block: &Ast) {
+        let Ast::Block(statements) = block else {
+            return;
+        };
+        for Statement { line, ast } in statements {
+            match ast {
+                Ast::Set { id, .. } => {
+                    if let Ast::Identifier(name) = id.as_ref() {
+                        self.variable(name);
+                    }
+                }
+                Ast::FunctionDefinition { id, params, body } => {
+                    let signature: Vec<String> = params
+                        .iter()
+                        .map(|(name, dtype)| format!("{}: {}", name, dtype))
+                        .collect();
+                    self.functions
+                        .entry(id.clone())
+                        .or_insert((format!("def {}({})", id, signature.join(", ")), *line));
+                    params.iter().for_each(|(name, _)| self.variable(name));
+                    self.collect(body);
+                }
+                Ast::If { block, .. } => self.collect(block),
+                Ast::ForLoop { element, block, .. } => {
+                    self.variable(&element.to_string());
+                    self.collect(block);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn variable(&mut self, name: &str) {
+        if !self.variables.iter().any(|v| v == name) {
+            self.variables.push(name.to_string());
+        }
+    }
+}
+
+fn uri(params: &Value) -> String {
+    params["textDocument"]["uri"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn position(line: usize, character: usize) -> Value {
+    json!({ "line": line, "character": character })
+}
+
+/// Range on one line, lines and characters starting at 0
+fn range(line: usize, start: usize, end: usize) -> Value {
+    json!({ "start": position(line, start), "end": position(line, end) })
+}
+
+/// Reads one message: headers, a blank line, then `Content-Length` bytes of JSON. [`None`]
+/// once the input ends. The Debug Adapter Protocol sends its messages the same way.
+pub(crate) fn read_message(input: &mut impl BufRead) -> Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse::<usize>()?);
+        }
+    }
+    let length = length.ok_or_else(|| anyhow!("Message without a Content-Length header"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .context("Message is not JSON")
+}
+
+/// Writes one message the way [`read_message`] reads it
+pub(crate) fn write_message(output: &mut impl Write, message: &Value) -> Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()?;
+    Ok(())
+}
@@ -1,84 +1,531 @@
-use clap::Parser;
-use program::{Program, ReturnType};
-use std::collections::HashMap;
+use clap::{Parser, Subcommand};
 use std::fs;
+use std::io::{BufReader, Read};
 use std::process::exit;
-mod util;
-use util::shell;
+use std::time::Duration;
+use teolang::program::{
+    bytecode,
+    check::{self, Severity, Typing},
+    debug::Stopped,
+    format,
+    native::NativeRegistry,
+    optimize, parser,
+    parser::Ast,
+    Data, Engine, Limits, Program, ProgramBuilder, ReturnType, RuntimeError,
+};
+use teolang::util::shell;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use rust_decimal::prelude::*;
+use rust_decimal_macros::dec;
+use serde_json::json;
 
-mod program;
+mod debug;
+mod lsp;
+mod repl;
+mod test;
+mod trace;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Read from <FILE_NAME>
-    #[arg(short, long)]
-    file_name: String,
-    /// Only parse the code and exit
-    #[arg(long, default_value_t = false)]
-    only_parse: bool,
+    #[command(subcommand)]
+    command: Command,
     /// Enable features from <FEATURES> (Features still need to be enable in build step)
-    #[arg(long, value_delimiter = ',', use_value_delimiter = true)]
+    #[arg(long, global = true, value_delimiter = ',', use_value_delimiter = true)]
     features: Vec<String>,
+    /// Stop the program after <TIMEOUT> seconds
+    #[arg(long, global = true)]
+    timeout: Option<f64>,
+    /// Run with <ENGINE>: `bytecode`, or `tree` to walk the syntax tree instead
+    #[arg(long, global = true, default_value = "bytecode")]
+    engine: Engine,
+    /// Print the syntax tree and errors as <FORMAT>
+    #[arg(long, global = true, value_enum, default_value_t = Format::Text)]
+    format: Format,
 }
 
-fn main() -> Result<()> {
+/// How `teo` prints what tools may want to read
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    /// For people
+    Text,
+    /// One JSON object per line, each with the `version` of its schema and a `type`
+    Json,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run a program
+    Run {
+        #[command(flatten)]
+        source: Source,
+        /// Fold constants and drop unreachable code before running
+        #[arg(short = 'O', long, default_value_t = false)]
+        optimize: bool,
+        /// Show each statement run, the values it computes and the variables it sets (as JSON
+        /// lines with `--format json`)
+        #[arg(long, default_value_t = false)]
+        trace: bool,
+    },
+    /// Run a program one statement at a time, stopping at breakpoints
+    Debug {
+        #[command(flatten)]
+        source: Source,
+        /// Talk to an editor over the Debug Adapter Protocol on stdin and stdout instead; the
+        /// editor says which program to run
+        #[arg(long, default_value_t = false)]
+        dap: bool,
+        /// Read the debugger's commands from <FILE>, leaving stdin to the program (otherwise
+        /// both read stdin, a line at a time)
+        #[arg(long, value_name = "FILE", conflicts_with = "dap")]
+        commands: Option<String>,
+    },
+    /// Look for mistakes in a program without running it
+    Check {
+        #[command(flatten)]
+        source: Source,
+        /// Also report values whose type cannot be known before running
+        #[arg(long, default_value_t = false)]
+        strict: bool,
+    },
+    /// Format programs in place (`-` formats stdin to stdout)
+    Fmt {
+        /// Files to format
+        #[arg(value_name = "FILE", required = true)]
+        files: Vec<String>,
+        /// Only check that the files are formatted; exit with 1 if one is not
+        #[arg(long, default_value_t = false)]
+        check: bool,
+    },
+    /// Type code and run it one line at a time
+    Repl,
+    /// Help editors with Teo code, over the Language Server Protocol on stdin and stdout
+    Lsp,
+    /// Run the `test_` functions of programs, each on its own; exit with 1 if one fails
+    Test {
+        /// Files to test
+        #[arg(value_name = "FILE", required = true)]
+        files: Vec<String>,
+        /// Only run the tests whose name contains <FILTER>
+        #[arg(long)]
+        filter: Option<String>,
+    },
+    /// Print the syntax tree of a program
+    Ast {
+        #[command(flatten)]
+        source: Source,
+        /// Show the tree after constant folding and dead code removal
+        #[arg(short = 'O', long, default_value_t = false)]
+        optimize: bool,
+        /// Show the compiled bytecode instead of the tree
+        #[arg(long, default_value_t = false)]
+        bytecode: bool,
+    },
+}
+
+/// Exit code for `teo check` finding errors, like `teo fmt --check` and `teo test` finding
+/// problems
+const EXIT_CHECK: i32 = 1;
+/// Exit code for a command line that makes no sense, like a missing file name
+const EXIT_USAGE: i32 = 64;
+/// Exit code for code that does not parse or compile
+const EXIT_PARSE: i32 = 65;
+/// Exit code for a file that cannot be read (or, for `teo fmt`, written)
+const EXIT_NO_INPUT: i32 = 66;
+/// Exit code for runtime errors without a code of their own (see [`RuntimeError::exit_code`])
+const EXIT_RUNTIME: i32 = 70;
+
+/// An error that stops `teo`, with the code to exit with
+struct Failure {
+    code: i32,
+    error: anyhow::Error,
+}
+
+impl Failure {
+    fn new(code: i32, error: anyhow::Error) -> Self {
+        Failure { code, error }
+    }
+}
+
+impl Failure {
+    /// `{"version", "type": "error", "code", "message", "causes", "location"}`, the location
+    /// being the `line` and `column` of a parse error, null otherwise
+    fn to_json(&self) -> serde_json::Value {
+        let location = parser::error_location(&self.error)
+            .map(|(line, column)| json!({ "line": line, "column": column }));
+        let causes: Vec<String> = self.error.chain().skip(1).map(|c| c.to_string()).collect();
+        json!({
+            "version": parser::JSON_VERSION,
+            "type": "error",
+            "code": self.code,
+            "message": self.error.to_string(),
+            "causes": causes,
+            "location": location,
+        })
+    }
+}
+
+/// Runtime errors, with the code of the [`RuntimeError`] behind them if any
+impl From<anyhow::Error> for Failure {
+    fn from(error: anyhow::Error) -> Self {
+        let code = error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<RuntimeError>())
+            .map_or(EXIT_RUNTIME, RuntimeError::exit_code);
+        Failure { code, error }
+    }
+}
+
+/// Where the code comes from
+#[derive(clap::Args, Debug)]
+struct Source {
+    /// Run <CODE> instead of a file
+    #[arg(short = 'e', long = "eval")]
+    code: Option<String>,
+    /// File to read (`-` for stdin, not needed with `-e`), then arguments for the program
+    #[arg(
+        value_name = "FILE|ARGS",
+        trailing_var_arg = true,
+        allow_hyphen_values = true
+    )]
+    inputs: Vec<String>,
+}
+
+impl Source {
+    /// Name to show in messages, the code and the arguments for the program
+    fn read(&self) -> Result<(String, String, Vec<String>), Failure> {
+        if let Some(code) = &self.code {
+            return Ok(("-e".to_string(), code.clone(), self.inputs.clone()));
+        }
+        let (file_name, args) = self.inputs.split_first().ok_or_else(|| {
+            Failure::new(
+                EXIT_USAGE,
+                anyhow!("Give a file to read, `-` for stdin, or code with -e"),
+            )
+        })?;
+        let code = if file_name == "-" {
+            let mut code = String::new();
+            std::io::stdin()
+                .read_to_string(&mut code)
+                .context("Failed to read from stdin")
+                .map_err(|e| Failure::new(EXIT_NO_INPUT, e))?;
+            code
+        } else {
+            fs::read_to_string(file_name)
+                .with_context(|| format!("Failed to read file from {}", file_name))
+                .map_err(|e| Failure::new(EXIT_NO_INPUT, e))?
+        };
+        Ok((file_name.clone(), code, args.to_vec()))
+    }
+
+    /// Reads and parses the code; also returns the arguments for the program
+    fn parse(&self) -> Result<(Ast, Vec<String>), Failure> {
+        let (name, code, args) = self.read()?;
+        let ast = Ast::parse_code(&code)
+            .with_context(|| format!("Failed to parse file from {}", name))
+            .map_err(|e| Failure::new(EXIT_PARSE, e))?;
+        Ok((ast, args))
+    }
+}
+
+/// The standard functions and those enabled by `--features`
+fn natives(args: &Args, shell: &mut shell::Shell) -> Result<NativeRegistry> {
+    let mut natives = NativeRegistry::standard();
+    for feature in &args.features {
+        if let Err(e) = natives.enable(feature) {
+            shell.warn(e)?;
+        }
+    }
+    Ok(natives)
+}
+
+/// Creates a program with the standard functions, the features and the limits of `args`
+fn builder(args: &Args, shell: &mut shell::Shell) -> Result<ProgramBuilder> {
+    Ok(Program::builder()
+        .natives(natives(args, shell)?)
+        .engine(args.engine)
+        .limits(Limits {
+            timeout: args.timeout.map(Duration::from_secs_f64),
+            ..Limits::default()
+        }))
+}
+
+fn main() {
     let mut shell = shell::Shell::new();
     let args = Args::parse();
-    let vec_ast = program::parser::Ast::parse_code(
-        fs::read_to_string(&args.file_name)
-            .with_context(|| {
-                let _ = shell.error("File error");
-                format!("Failed to read file from {}", args.file_name)
-            })?
-            .as_str(),
-    )
-    .with_context(|| {
-        let _ = shell.error("Parse error");
-        format!("Failed to parse file from {}", args.file_name)
-    })?;
-    if args.only_parse {
-        println!("{:#?}", vec_ast);
-        exit(0);
+    let code = match teo(&args, &mut shell) {
+        Ok(code) => code,
+        Err(failure) if args.format == Format::Json => {
+            let _ = shell.print_json(&failure.to_json());
+            failure.code
+        }
+        Err(failure) => {
+            let error = &failure.error;
+            // Nested calls wrap an error once per level, so only the first and last are shown
+            let message = if error.chain().count() > 1 {
+                format!("{}\n\nCaused by:\n  {}", error, error.root_cause())
+            } else {
+                error.to_string()
+            };
+            let _ = shell.error(message);
+            failure.code
+        }
     };
-    let mut features_list = vec![
-        "return".to_owned(),
-        "print".to_owned(),
-        "input".to_owned(),
-        "split".to_owned(),
-        "inputf".to_owned(),
-    ];
-    for feature in &args.features {
-        if !features_list.contains(&feature) {
-            features_list.push(feature.to_string());
+    exit(code)
+}
+
+/// Runs the command of `args` and returns the code to exit with
+fn teo(args: &Args, shell: &mut shell::Shell) -> Result<i32, Failure> {
+    match &args.command {
+        Command::Run {
+            source,
+            optimize,
+            trace,
+        } => {
+            let (ast, script_args) = source.parse()?;
+            let ast = if *optimize {
+                optimize::optimize(ast)
+            } else {
+                ast
+            };
+            let mut builder = builder(args, shell)?.commands(ast).args(script_args);
+            if *trace {
+                let tracer = trace::Tracer::new(args.format);
+                if let Some(output) = tracer.output() {
+                    builder = builder.output(output);
+                }
+                builder = builder.debugger(tracer);
+            }
+            run(builder.build()?, shell)
+        }
+        Command::Debug { dap: true, .. } => {
+            debug::dap::run(builder(args, shell)?)?;
+            Ok(0)
+        }
+        Command::Debug {
+            source,
+            dap: false,
+            commands,
+        } => {
+            let (name, code, script_args) = source.read()?;
+            let ast = Ast::parse_code(&code)
+                .with_context(|| format!("Failed to parse file from {}", name))
+                .map_err(|e| Failure::new(EXIT_PARSE, e))?;
+            let mut terminal = debug::Terminal::new(&code);
+            if let Some(path) = commands {
+                let file = fs::File::open(path)
+                    .with_context(|| format!("Failed to read file from {}", path))
+                    .map_err(|e| Failure::new(EXIT_NO_INPUT, e))?;
+                terminal = terminal.commands(BufReader::new(file));
+            }
+            let program = builder(args, shell)?
+                .commands(ast)
+                .args(script_args)
+                .debugger(terminal)
+                .build()?;
+            run(program, shell)
+        }
+        Command::Check { source, strict } => {
+            let (ast, _) = source.parse()?;
+            let typing = if *strict {
+                Typing::Strict
+            } else {
+                Typing::Gradual
+            };
+            let diagnostics = check::check(&ast, &natives(args, shell)?, typing);
+            for diagnostic in &diagnostics {
+                let severity = match diagnostic.severity {
+                    Severity::Error => "error",
+                    Severity::Warning => "warning",
+                };
+                match (args.format, diagnostic.severity) {
+                    (Format::Json, _) => shell.print_json(&json!({
+                        "version": parser::JSON_VERSION,
+                        "type": "diagnostic",
+                        "severity": severity,
+                        "line": diagnostic.line,
+                        "message": diagnostic.message,
+                    }))?,
+                    (Format::Text, Severity::Error) => shell.error(diagnostic)?,
+                    (Format::Text, Severity::Warning) => shell.warn(diagnostic)?,
+                }
+            }
+            let errors = diagnostics
+                .iter()
+                .filter(|d| d.severity == Severity::Error)
+                .count();
+            if errors > 0 {
+                return Err(Failure::new(
+                    EXIT_CHECK,
+                    anyhow!("Found {} error(s)", errors),
+                ));
+            }
+            bytecode::compile(&ast)
+                .context("Failed to compile")
+                .map_err(|e| Failure::new(EXIT_PARSE, e))?;
+            match diagnostics.len() {
+                0 => shell.status("Checked", "no problems found")?,
+                warnings => shell.status("Checked", format!("{} warning(s)", warnings))?,
+            }
+            Ok(0)
+        }
+        Command::Fmt { files, check } => {
+            let mut unformatted = false;
+            for file in files {
+                unformatted |= fmt(file, *check, shell)?;
+            }
+            Ok(i32::from(*check && unformatted))
+        }
+        Command::Test { files, filter } => {
+            // Warn about unknown features once, not for every test
+            builder(args, shell)?;
+            let mut results = test::Results::default();
+            for file in files {
+                let source = Source {
+                    code: None,
+                    inputs: vec![file.clone()],
+                };
+                let (ast, _) = source.parse()?;
+                test::run(
+                    file,
+                    &ast,
+                    filter.as_deref(),
+                    || {
+                        builder(args, &mut shell::Shell::new())
+                            .expect("features were checked already")
+                    },
+                    args.format,
+                    shell,
+                    &mut results,
+                )?;
+            }
+            if args.format == Format::Text {
+                shell.status(
+                    "Finished",
+                    format!("{} passed, {} failed", results.passed, results.failed),
+                )?;
+            }
+            Ok(i32::from(results.failed > 0))
+        }
+        Command::Repl => {
+            // Warn about unknown features once, not on every `:reset`
+            builder(args, shell)?;
+            repl::run(shell, || {
+                builder(args, &mut shell::Shell::new()).expect("features were checked already")
+            })?;
+            Ok(0)
+        }
+        Command::Lsp => {
+            lsp::run(natives(args, shell)?)?;
+            Ok(0)
+        }
+        Command::Ast {
+            source,
+            optimize,
+            bytecode,
+        } => {
+            let (ast, _) = source.parse()?;
+            let ast = if *optimize {
+                optimize::optimize(ast)
+            } else {
+                ast
+            };
+            match (*bytecode, args.format) {
+                (true, Format::Text) => {
+                    let chunk = bytecode::compile(&ast).map_err(|e| Failure::new(EXIT_PARSE, e))?;
+                    println!("{:#?}", chunk);
+                }
+                (true, Format::Json) => {
+                    return Err(Failure::new(
+                        EXIT_USAGE,
+                        anyhow!("Bytecode has no JSON form, only the syntax tree does"),
+                    ))
+                }
+                (false, Format::Text) => println!("{:#?}", ast),
+                (false, Format::Json) => shell.print_json(&json!({
+                    "version": parser::JSON_VERSION,
+                    "type": "ast",
+                    "ast": ast,
+                }))?,
+            }
+            Ok(0)
         }
     }
-    let mut featureliststr = "".to_string();
-    for feature in &features_list {
-        featureliststr = featureliststr + "`" + &feature + "`" + " ";
+}
+
+/// Formats `file`, or with `check` only tells whether it needs to be. Returns whether it was
+/// not formatted already.
+fn fmt(file: &str, check: bool, shell: &mut shell::Shell) -> Result<bool, Failure> {
+    let source = Source {
+        code: None,
+        inputs: vec![file.to_string()],
+    };
+    let (name, code, _) = source.read()?;
+    let ast = Ast::parse_code(&code)
+        .with_context(|| format!("Failed to parse file from {}", name))
+        .map_err(|e| Failure::new(EXIT_PARSE, e))?;
+    let formatted = format::format(&ast);
+    // Never write code that does something else than what was there
+    let same = Ast::parse_code(&formatted).is_ok_and(|f| f.without_lines() == ast.without_lines());
+    if !same {
+        return Err(anyhow!(
+            "Formatting {} would change what it does, left it as is",
+            name
+        )
+        .into());
+    }
+    let unformatted = formatted != code;
+    if check {
+        if unformatted {
+            shell.status_with_color("Unformatted", &name, termcolor::Color::Red)?;
+        }
+    } else if file == "-" {
+        print!("{}", formatted);
+    } else if unformatted {
+        fs::write(file, &formatted)
+            .with_context(|| format!("Failed to write file to {}", name))
+            .map_err(|e| Failure::new(EXIT_NO_INPUT, e))?;
+        shell.status("Formatted", &name)?;
     }
-    let mut program: Program = Program {
-        commands: vec_ast,
-        current_line: 0,
-        variable: HashMap::new(),
-        function: HashMap::new(),
-        std_commands: features_list,
+    Ok(unformatted)
+}
+
+/// Runs `program` and returns the code to exit with (see [`exit_code`]). Returned Strings and
+/// Arrays are printed.
+fn run(mut program: Program, shell: &mut shell::Shell) -> Result<i32, Failure> {
+    let functions: Vec<String> = program
+        .natives
+        .names()
+        .iter()
+        .map(|name| format!("`{}`", name))
+        .collect();
+    shell.status("Running", format!("with functions {}", functions.join(" ")))?;
+    let returned = match program.run() {
+        // Quitting the debugger is not a failure
+        Err(e) if e.chain().any(|cause| cause.is::<Stopped>()) => return Ok(0),
+        returned => returned?,
     };
-    shell
-        .status("Running", "with feature ".to_string() + &featureliststr)
-        .unwrap();
-    let output = program.run_loop(&mut Vec::new(), &mut shell);
-    if let Ok(returnval) = output {
-        match returnval {
-            ReturnType::Ok(program::Data::Number(e)) => {
-                exit(e.round().to_string().parse().unwrap())
-            }
-            ReturnType::None => exit(0),
-            _ => unimplemented!(),
+    if let ReturnType::Ok(value @ (Data::String(_) | Data::Array(_))) = &returned {
+        println!("{}", value.as_string());
+    }
+    Ok(exit_code(&returned))
+}
+
+/// Code to exit with once a program returned: a returned Number (rounded, modulo 256), 0 for
+/// `true` and 1 for `false`, otherwise 0
+fn exit_code(returned: &ReturnType) -> i32 {
+    match returned {
+        ReturnType::Ok(Data::Number(n)) => {
+            let code = n.round() % dec!(256);
+            let code = if code.is_sign_negative() {
+                code + dec!(256)
+            } else {
+                code
+            };
+            code.to_i32().unwrap_or(0)
         }
-    } else {
-        shell.error("We fucked up")
+        ReturnType::Ok(Data::Bool(b)) => i32::from(!b),
+        ReturnType::Ok(_) | ReturnType::None => 0,
     }
 }
@@ -0,0 +1,163 @@
+use super::debug::{DebugState, Debugger};
+use super::limits::ExecState;
+use super::native::{NativeFunction, NativeRegistry};
+use super::{optimize, parser, CancelToken, Data, Engine, Limits, Program, ProgramIo};
+use anyhow::Result;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+/// Builds a [`Program`] without filling every field by hand.
+///
+/// ```rust
+/// # use teolang::program::{Data, Program};
+/// let mut program = Program::builder()
+///     .source("total = price * count;")
+///     .global("price", 3)
+///     .global("count", 4)
+///     .build()
+///     .unwrap();
+/// program.run().unwrap();
+/// assert_eq!(program.get_global("total"), Some(&Data::from(12)));
+/// ```
+#[derive(Default)]
+pub struct ProgramBuilder {
+    source: Option<String>,
+    commands: Option<parser::Ast>,
+    variable: HashMap<String, Data>,
+    natives: Option<NativeRegistry>,
+    features: Vec<String>,
+    io: ProgramIo,
+    limits: Limits,
+    cancel: CancelToken,
+    engine: Engine,
+    optimize: bool,
+    args: Vec<String>,
+    debugger: Option<Box<dyn Debugger>>,
+}
+
+impl ProgramBuilder {
+    /// Code to run, parsed by [`ProgramBuilder::build`]
+    pub fn source(mut self, source: &str) -> Self {
+        self.source = Some(source.to_string());
+        self
+    }
+
+    /// Already parsed code to run, used instead of [`ProgramBuilder::source`]
+    pub fn commands(mut self, commands: parser::Ast) -> Self {
+        self.commands = Some(commands);
+        self
+    }
+
+    /// Sets a global variable before the program starts
+    pub fn global(mut self, name: &str, value: impl Into<Data>) -> Self {
+        self.variable.insert(name.to_string(), value.into());
+        self
+    }
+
+    /// Replaces the standard functions ([`NativeRegistry::standard`] by default)
+    pub fn natives(mut self, natives: NativeRegistry) -> Self {
+        self.natives = Some(natives);
+        self
+    }
+
+    /// Adds a native function next to the standard ones
+    pub fn native(mut self, function: impl NativeFunction + 'static) -> Self {
+        self.natives
+            .get_or_insert_with(NativeRegistry::standard)
+            .register(function);
+        self
+    }
+
+    /// Enables an opt-in standard function (see [`NativeRegistry::enable`])
+    pub fn feature(mut self, feature: &str) -> Self {
+        self.features.push(feature.to_string());
+        self
+    }
+
+    /// Where `input()` and `inputf()` read from (stdin by default)
+    pub fn input(mut self, input: Rc<RefCell<dyn BufRead>>) -> Self {
+        self.io.input = input;
+        self
+    }
+
+    /// Where `print()` writes to (stdout by default). Keep a clone of `output` to read what
+    /// the program printed.
+    pub fn output(mut self, output: Rc<RefCell<dyn Write>>) -> Self {
+        self.io.output = output;
+        self
+    }
+
+    /// Caps on steps, call depth and value size ([`Limits::default`] if not set)
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Token another thread can cancel to stop the program (a new one if not set)
+    pub fn cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// How the program runs ([`Engine::Bytecode`] by default)
+    pub fn engine(mut self, engine: Engine) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    /// Runs [`optimize::optimize`] over the code before it starts (off by default)
+    pub fn optimize(mut self, optimize: bool) -> Self {
+        self.optimize = optimize;
+        self
+    }
+
+    /// Arguments for the program, like the ones after the file name on the command line
+    pub fn args(mut self, args: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Stops before each statement to call `debugger`. Also makes the program run with
+    /// [`Engine::TreeWalker`].
+    pub fn debugger(mut self, debugger: impl Debugger + 'static) -> Self {
+        self.debugger = Some(Box::new(debugger));
+        self
+    }
+
+    /// Parses the source and creates the program
+    pub fn build(self) -> Result<Program> {
+        let commands = match (self.commands, self.source) {
+            (Some(commands), _) => commands,
+            (None, Some(source)) => parser::Ast::parse_code(&source)?,
+            (None, None) => parser::Ast::Block(vec![]),
+        };
+        let commands = if self.optimize {
+            optimize::optimize(commands)
+        } else {
+            commands
+        };
+        let mut natives = self.natives.unwrap_or_else(NativeRegistry::standard);
+        for feature in &self.features {
+            natives.enable(feature)?;
+        }
+        Ok(Program {
+            commands,
+            current_line: 0,
+            variable: self.variable,
+            function: HashMap::new(),
+            natives,
+            io: self.io,
+            engine: match self.debugger {
+                Some(_) => Engine::TreeWalker,
+                None => self.engine,
+            },
+            args: self.args.into(),
+            state: Rc::new(ExecState::new(self.limits, self.cancel)),
+            debugger: self
+                .debugger
+                .map(|debugger| Rc::new(DebugState::new(debugger))),
+        })
+    }
+}
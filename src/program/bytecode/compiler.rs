@@ -0,0 +1,305 @@
+use super::{Chunk, Function, Instruction, Operator};
+use crate::program::parser::Ast;
+use crate::program::{Data, DataType};
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Compiles the statements of a program (an [`Ast::Block`])
+pub fn compile(program: &Ast) -> Result<Chunk> {
+    let mut compiler = Compiler::default();
+    compiler.block(program)?;
+    Ok(compiler.chunk)
+}
+
+/// Compiles an [`Ast::FunctionDefinition`]
+pub(super) fn compile_function(definition: &Ast) -> Result<Function> {
+    let (id, params, body) = match definition {
+        Ast::FunctionDefinition { id, params, body } => (id, params, body),
+        other => return Err(anyhow!("`{:?}` is not a function!", other)),
+    };
+    let mut compiler = Compiler::default();
+    let mut typed_params = Vec::with_capacity(params.len());
+    for (name, dtype) in params {
+        compiler.slot(name);
+        typed_params.push((Rc::from(name.as_str()), dtype.parse::<DataType>()?));
+    }
+    compiler.block(body)?;
+    Ok(Function {
+        name: Rc::from(id.as_str()),
+        params: typed_params,
+        chunk: compiler.chunk,
+        definition: definition.clone(),
+    })
+}
+
+#[derive(Default)]
+struct Compiler {
+    chunk: Chunk,
+    slots: HashMap<String, u32>,
+    names: HashMap<String, u32>,
+}
+
+impl Compiler {
+    fn emit(&mut self, instruction: Instruction) -> u32 {
+        self.chunk.code.push(instruction);
+        self.chunk.code.len() as u32 - 1
+    }
+
+    /// Position the next instruction will have
+    fn here(&self) -> u32 {
+        self.chunk.code.len() as u32
+    }
+
+    /// Points the jump at `at` to the next instruction
+    fn patch(&mut self, at: u32) {
+        let target = self.here();
+        match &mut self.chunk.code[at as usize] {
+            Instruction::Jump(to) | Instruction::JumpUnlessTrue(to) => *to = target,
+            Instruction::IterNext { exit, .. } => *exit = target,
+            other => unreachable!("{:?} does not jump", other),
+        }
+    }
+
+    fn slot(&mut self, name: &str) -> u32 {
+        if let Some(slot) = self.slots.get(name) {
+            return *slot;
+        }
+        let slot = self.chunk.slots.len() as u32;
+        self.chunk.slots.push(Rc::from(name));
+        self.slots.insert(name.to_string(), slot);
+        slot
+    }
+
+    fn name(&mut self, name: &str) -> u32 {
+        if let Some(index) = self.names.get(name) {
+            return *index;
+        }
+        let index = self.chunk.names.len() as u32;
+        self.chunk.names.push(Rc::from(name));
+        self.names.insert(name.to_string(), index);
+        index
+    }
+
+    fn constant(&mut self, data: Data) -> u32 {
+        self.chunk.constants.push(data);
+        self.chunk.constants.len() as u32 - 1
+    }
+
+    fn fail(&mut self, message: &str) {
+        let message = self.name(message);
+        self.emit(Instruction::Fail(message));
+    }
+
+    fn block(&mut self, block: &Ast) -> Result<()> {
+        match block {
+            Ast::Block(statements) => {
+                for statement in statements {
+                    if let Ast::Comment(_) = statement.ast {
+                        continue;
+                    }
+                    self.emit(Instruction::Step);
+                    self.statement(&statement.ast)?;
+                }
+                Ok(())
+            }
+            other => Err(anyhow!("Expected a block, got {:?}", other)),
+        }
+    }
+
+    /// Compiles the body of an `if` or a `for`, which forgets the functions it defines
+    fn scoped_block(&mut self, block: &Ast) -> Result<()> {
+        let defines = matches!(block, Ast::Block(statements)
+            if statements.iter().any(|s| matches!(s.ast, Ast::FunctionDefinition { .. })));
+        if defines {
+            self.emit(Instruction::EnterScope);
+        }
+        self.block(block)?;
+        if defines {
+            self.emit(Instruction::ExitScope);
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Ast) -> Result<()> {
+        match statement {
+            Ast::Set { id, op, expr } => {
+                self.expression(expr)?;
+                let op = op.as_deref().map(str::parse::<Operator>).transpose()?;
+                match id.as_ref() {
+                    Ast::ArrayAccess {
+                        expr: array,
+                        whereto,
+                    } => {
+                        let slot = match array.as_ref() {
+                            Ast::Identifier(id) => self.slot(id),
+                            other => {
+                                return Err(anyhow!("Cannot assign to an element of {:?}", other))
+                            }
+                        };
+                        self.expression(whereto)?;
+                        self.emit(Instruction::StoreIndex { slot, op });
+                    }
+                    id => {
+                        let slot = self.slot(&id.to_string());
+                        match op {
+                            Some(op) => self.emit(Instruction::Update { slot, op }),
+                            None => self.emit(Instruction::Store(slot)),
+                        };
+                    }
+                }
+            }
+            Ast::If { condition, block } => {
+                self.expression(condition)?;
+                let skip = self.emit(Instruction::JumpUnlessTrue(0));
+                self.scoped_block(block)?;
+                self.patch(skip);
+            }
+            Ast::FunctionDefinition { .. } => {
+                let function = compile_function(statement)?;
+                self.chunk.functions.push(Rc::new(function));
+                let index = self.chunk.functions.len() as u32 - 1;
+                self.emit(Instruction::DefineFunction(index));
+            }
+            Ast::FunctionCall { id, args } if id == "return" => {
+                if !cfg!(feature = "return") {
+                    self.fail("Function return is not enabled!");
+                } else if let [arg] = args.as_slice() {
+                    self.expression(arg)?;
+                    self.emit(Instruction::Return);
+                } else {
+                    self.fail("Need to return only one value!");
+                }
+            }
+            Ast::ForLoop {
+                element,
+                elements,
+                block,
+            } => {
+                match elements.as_ref() {
+                    Ast::Range {
+                        start,
+                        end,
+                        step,
+                        inclusive,
+                    } => {
+                        self.expression(start)?;
+                        self.expression(end)?;
+                        if let Some(step) = step {
+                            self.expression(step)?;
+                        }
+                        self.emit(Instruction::IterRange {
+                            inclusive: *inclusive,
+                            step: step.is_some(),
+                        });
+                    }
+                    elements => {
+                        self.expression(elements)?;
+                        self.emit(Instruction::IterArray);
+                    }
+                }
+                let slot = self.slot(&element.to_string());
+                let next = self.emit(Instruction::IterNext { slot, exit: 0 });
+                self.emit(Instruction::Step);
+                self.scoped_block(block)?;
+                self.emit(Instruction::Jump(next));
+                self.patch(next);
+            }
+            expr => {
+                self.expression(expr)?;
+                self.emit(Instruction::Pop);
+            }
+        }
+        Ok(())
+    }
+
+    fn expression(&mut self, expr: &Ast) -> Result<()> {
+        match expr {
+            Ast::Int(n) => {
+                let constant = self.constant(Data::Number(*n));
+                self.emit(Instruction::Constant(constant));
+            }
+            Ast::Bool(b) => {
+                let constant = self.constant(Data::Bool(*b));
+                self.emit(Instruction::Constant(constant));
+            }
+            Ast::String(s) => {
+                let constant = self.constant(Data::String(s.as_str().into()));
+                self.emit(Instruction::Constant(constant));
+            }
+            Ast::Identifier(id) => {
+                let slot = self.slot(id);
+                self.emit(Instruction::Load(slot));
+            }
+            Ast::BinaryOp { op, left, right } => {
+                self.expression(left)?;
+                self.expression(right)?;
+                self.emit(Instruction::Binary(op.parse()?));
+            }
+            Ast::Interpolated(parts) => {
+                for part in parts {
+                    self.expression(part)?;
+                }
+                self.emit(Instruction::Interpolate(parts.len() as u32));
+            }
+            Ast::Range {
+                start,
+                end,
+                step,
+                inclusive,
+            } => {
+                self.expression(start)?;
+                self.expression(end)?;
+                if let Some(step) = step {
+                    self.expression(step)?;
+                }
+                self.emit(Instruction::MakeRange {
+                    inclusive: *inclusive,
+                    step: step.is_some(),
+                });
+            }
+            Ast::Array(elements) => {
+                for element in elements {
+                    self.expression(element)?;
+                }
+                self.emit(Instruction::MakeArray(elements.len() as u32));
+            }
+            Ast::ArrayAccess { expr, whereto } => {
+                self.expression(expr)?;
+                match whereto.as_ref() {
+                    Ast::AstSlice { from, to } => {
+                        if let Some(from) = from {
+                            self.expression(from)?;
+                        }
+                        if let Some(to) = to {
+                            self.expression(to)?;
+                        }
+                        self.emit(Instruction::Slice {
+                            from: from.is_some(),
+                            to: to.is_some(),
+                        });
+                    }
+                    index => {
+                        self.expression(index)?;
+                        self.emit(Instruction::Index);
+                    }
+                }
+            }
+            Ast::FunctionCall { id, .. } if id == "return" => {
+                self.fail("`return` can only be used as a statement!");
+            }
+            Ast::FunctionCall { id, args } => {
+                for arg in args {
+                    self.expression(arg)?;
+                }
+                let name = self.name(id);
+                self.emit(Instruction::Call {
+                    name,
+                    args: args.len() as u32,
+                });
+            }
+            other => return Err(anyhow!("Invalid AST node: {:?}", other)),
+        }
+        Ok(())
+    }
+}
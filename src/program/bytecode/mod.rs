@@ -0,0 +1,220 @@
+//! Bytecode for a stack based virtual machine, the default way a [`Program`] runs.
+//!
+//! [`compile`] turns a block of [`Ast`] into a [`Chunk`]: a flat list of [`Instruction`]s in
+//! which variables are resolved to numbered slots of the function they belong to, so running
+//! it never clones blocks or variable maps. The tree walker ([`Engine::TreeWalker`]) is kept
+//! to compare against.
+//!
+//! ```rust
+//! # use teolang::program::bytecode::{self, Instruction};
+//! # use teolang::program::parser::Ast;
+//! let chunk = bytecode::compile(&Ast::parse_code("x = 1; x += 2;")?)?;
+//! assert_eq!(&*chunk.slots[0], "x");
+//! assert!(chunk.code.contains(&Instruction::Store(0)));
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! [`Engine::TreeWalker`]: super::Engine::TreeWalker
+
+use super::parser::Ast;
+use super::{Data, DataType, Program, ReturnType};
+use anyhow::{anyhow, Result};
+use std::rc::Rc;
+
+mod compiler;
+mod vm;
+
+pub use compiler::compile;
+
+/// Compiled code of a function or of a whole program
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Chunk {
+    pub code: Vec<Instruction>,
+    /// Literals used by [`Instruction::Constant`]
+    pub constants: Vec<Data>,
+    /// Function names and error messages used by [`Instruction::Call`] and
+    /// [`Instruction::Fail`]
+    pub names: Vec<Rc<str>>,
+    /// Functions defined by [`Instruction::DefineFunction`]
+    pub functions: Vec<Rc<Function>>,
+    /// Name of each variable slot. Parameters come first.
+    pub slots: Vec<Rc<str>>,
+}
+
+/// A compiled `def`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: Rc<str>,
+    pub params: Vec<(Rc<str>, DataType)>,
+    pub chunk: Chunk,
+    /// The `def` it was compiled from, kept for [`Program::function`]
+    pub definition: Ast,
+}
+
+/// One step of a [`Chunk`]. Operands index the pools of the chunk; jump targets index
+/// [`Chunk::code`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// Counts a statement or loop iteration against the limits of the program
+    Step,
+    /// Pushes a constant
+    Constant(u32),
+    /// Pushes the value of a variable
+    Load(u32),
+    /// Pops a value into a variable
+    Store(u32),
+    /// Pops a value and applies it to a variable (`x += value`)
+    Update {
+        slot: u32,
+        op: Operator,
+    },
+    /// Pops an index then a value and stores the value in the array held by a variable,
+    /// applying `op` to the current element first if given (`x[i] += value`)
+    StoreIndex {
+        slot: u32,
+        op: Option<Operator>,
+    },
+    /// Pops the right then the left operand and pushes the result
+    Binary(Operator),
+    /// Pops that many values into a new array
+    MakeArray(u32),
+    /// Pops that many values and pushes them joined as a string
+    Interpolate(u32),
+    /// Pops the bounds (and the step, if any) and pushes every number of the range
+    MakeRange {
+        inclusive: bool,
+        step: bool,
+    },
+    /// Pops an index then an array and pushes the element
+    Index,
+    /// Pops the bounds given and an array and pushes the slice
+    Slice {
+        from: bool,
+        to: bool,
+    },
+    /// Pops the arguments and calls the function named `name`
+    Call {
+        name: u32,
+        args: u32,
+    },
+    /// Drops the top of the stack
+    Pop,
+    Jump(u32),
+    /// Pops a condition and jumps unless it is `true`. Fails for anything but a Bool.
+    JumpUnlessTrue(u32),
+    /// Pops the bounds (and the step, if any) and starts iterating over the range
+    IterRange {
+        inclusive: bool,
+        step: bool,
+    },
+    /// Pops an array and starts iterating over it
+    IterArray,
+    /// Stores the next item of the innermost loop in a variable, or ends the loop and jumps
+    /// to `exit`
+    IterNext {
+        slot: u32,
+        exit: u32,
+    },
+    /// Starts a block whose function definitions are forgotten when it ends
+    EnterScope,
+    /// Ends the innermost block started by [`Instruction::EnterScope`]
+    ExitScope,
+    /// Makes a function of [`Chunk::functions`] callable
+    DefineFunction(u32),
+    /// Pops a value and returns it from the function (or the program)
+    Return,
+    /// Fails with a message
+    Fail(u32),
+}
+
+/// Binary operator of an [`Instruction`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    /// Factorial (`n!`), the right operand is ignored
+    Fac,
+}
+
+impl Operator {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Operator::Add => "+",
+            Operator::Sub => "-",
+            Operator::Mul => "*",
+            Operator::Div => "/",
+            Operator::Pow => "^",
+            Operator::Eq => "==",
+            Operator::Ne => "!=",
+            Operator::Lt => "<",
+            Operator::Gt => ">",
+            Operator::Le => "<=",
+            Operator::Ge => ">=",
+            Operator::Fac => "!",
+        }
+    }
+}
+
+impl std::str::FromStr for Operator {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "+" => Operator::Add,
+            "-" => Operator::Sub,
+            "*" => Operator::Mul,
+            "/" => Operator::Div,
+            "^" => Operator::Pow,
+            "==" => Operator::Eq,
+            "!=" => Operator::Ne,
+            "<" => Operator::Lt,
+            ">" => Operator::Gt,
+            "<=" => Operator::Le,
+            ">=" => Operator::Ge,
+            "!" => Operator::Fac,
+            _ => return Err(anyhow!("{} is not a valid binary operator", s)),
+        })
+    }
+}
+
+/// Runs the program from the top. Globals and top-level functions are read from and written
+/// back to `program`, like the tree walker does.
+pub(super) fn run(program: &mut Program) -> Result<ReturnType> {
+    let main = compile(&program.commands)?;
+    let mut slots: Vec<Option<Data>> = main
+        .slots
+        .iter()
+        .map(|name| program.variable.get(&**name).cloned())
+        .collect();
+    let mut vm = vm::Vm::new(program)?;
+    let result = vm.execute(&main, &mut slots);
+    let functions = vm.into_functions();
+    for (name, value) in main.slots.iter().zip(slots) {
+        if let Some(value) = value {
+            program.variable.insert(name.to_string(), value);
+        }
+    }
+    for function in functions {
+        program
+            .function
+            .insert(function.name.to_string(), function.definition.clone());
+    }
+    Ok(match result? {
+        Some(value) => ReturnType::Ok(value),
+        None => ReturnType::None,
+    })
+}
+
+/// Calls a standard function or a function the program defined
+pub(super) fn call(program: &Program, name: &str, args: Vec<Data>) -> Result<Data> {
+    vm::Vm::new(program)?.invoke(name, args)
+}
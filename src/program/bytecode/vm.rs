@@ -0,0 +1,326 @@
+use super::compiler::compile_function;
+use super::{Chunk, Function, Instruction};
+use crate::program::Program;
+use crate::program::{
+    binary_op, index_array, number_range, slice_array, Data, NumberRange, RuntimeError,
+};
+use anyhow::{anyhow, Context, Result};
+use rust_decimal::prelude::ToPrimitive;
+use std::rc::Rc;
+
+/// Runs [`Chunk`]s for a [`Program`]
+pub(super) struct Vm<'p> {
+    program: &'p Program,
+    /// Functions that can be called, innermost last. Blocks and calls truncate it back when
+    /// they end, so functions are only visible where the tree walker would see them.
+    functions: Vec<Rc<Function>>,
+}
+
+/// A loop being run by [`Instruction::IterNext`]
+enum Iteration {
+    Range(NumberRange),
+    /// Elements and the position of the next one
+    Array(Rc<Vec<Data>>, usize),
+}
+
+impl Iterator for Iteration {
+    type Item = Data;
+
+    fn next(&mut self) -> Option<Data> {
+        match self {
+            Iteration::Range(range) => range.next().map(Data::Number),
+            Iteration::Array(array, next) => {
+                let item = array.get(*next).cloned();
+                *next += 1;
+                item
+            }
+        }
+    }
+}
+
+fn pop(stack: &mut Vec<Data>) -> Data {
+    stack.pop().expect("bytecode popped an empty stack")
+}
+
+/// Pops `count` values, first pushed first
+fn pop_many(stack: &mut Vec<Data>, count: u32) -> Vec<Data> {
+    stack.split_off(stack.len() - count as usize)
+}
+
+/// Pops the bounds pushed by [`Instruction::MakeRange`] or [`Instruction::IterRange`]
+fn pop_range(stack: &mut Vec<Data>, inclusive: bool, step: bool) -> Result<NumberRange> {
+    let step = step.then(|| pop(stack));
+    let end = pop(stack);
+    let start = pop(stack);
+    number_range(start, end, step, inclusive)
+}
+
+impl<'p> Vm<'p> {
+    /// Creates a machine that can call every function `program` already defined
+    pub(super) fn new(program: &'p Program) -> Result<Vm<'p>> {
+        let functions = program
+            .function
+            .values()
+            .map(|definition| compile_function(definition).map(Rc::new))
+            .collect::<Result<_>>()?;
+        Ok(Vm { program, functions })
+    }
+
+    /// Functions defined at the top level of the program
+    pub(super) fn into_functions(self) -> Vec<Rc<Function>> {
+        self.functions
+    }
+
+    fn function(&self, name: &str) -> Option<&Rc<Function>> {
+        self.functions.iter().rev().find(|f| &*f.name == name)
+    }
+
+    /// Calls a standard function or a function defined so far
+    pub(super) fn invoke(&mut self, name: &str, args: Vec<Data>) -> Result<Data> {
+        if let Some(native) = self.program.natives.get(name).cloned() {
+            self.program.invoke_native(native.as_ref(), args)
+        } else if let Some(function) = self.function(name).cloned() {
+            self.call_function(&function, args)
+        } else {
+            Err(RuntimeError::UndefinedFunction {
+                name: name.to_string(),
+            }
+            .into())
+        }
+    }
+
+    fn call_function(&mut self, function: &Function, args: Vec<Data>) -> Result<Data> {
+        let id = &function.name;
+        if function.params.len() < args.len() {
+            return Err(RuntimeError::WrongArguments {
+                message: format!("Too many argument for `{}`!", id),
+            }
+            .into());
+        }
+        if function.params.len() > args.len() {
+            return Err(RuntimeError::WrongArguments {
+                message: format!("Not enough argument for `{}`!", id),
+            }
+            .into());
+        }
+        let mut slots: Vec<Option<Data>> = Vec::with_capacity(function.chunk.slots.len());
+        for ((name, dtype), value) in function.params.iter().zip(args) {
+            if !dtype.matches(&value) {
+                return Err(RuntimeError::WrongArguments {
+                    message: format!(
+                        "Wrong type for function `{}`: expected {} for `{}`, got {:?}!",
+                        id, dtype, name, value
+                    ),
+                }
+                .into());
+            }
+            slots.push(Some(value));
+        }
+        slots.resize(function.chunk.slots.len(), None);
+        let _depth = self.program.state.enter_call()?;
+        let visible = self.functions.len();
+        let result = self.execute(&function.chunk, &mut slots);
+        self.functions.truncate(visible);
+        Ok(result
+            .with_context(|| format!("Function `{}` failed", id))?
+            .unwrap_or(Data::Number(0.into())))
+    }
+
+    /// Runs `chunk` with its variables in `slots`; returns the value of `return`, if any
+    pub(super) fn execute(
+        &mut self,
+        chunk: &Chunk,
+        slots: &mut [Option<Data>],
+    ) -> Result<Option<Data>> {
+        let mut scopes = Vec::new();
+        let result = self.execute_in(chunk, slots, &mut scopes);
+        // Functions defined in blocks that were left early (by `return` or an error) are
+        // forgotten as well
+        if let Some(&visible) = scopes.first() {
+            self.functions.truncate(visible);
+        }
+        result
+    }
+
+    fn execute_in(
+        &mut self,
+        chunk: &Chunk,
+        slots: &mut [Option<Data>],
+        scopes: &mut Vec<usize>,
+    ) -> Result<Option<Data>> {
+        let state = &self.program.state;
+        let limits = &state.limits;
+        let mut stack: Vec<Data> = Vec::new();
+        let mut loops: Vec<Iteration> = Vec::new();
+        let mut ip = 0;
+        while let Some(&instruction) = chunk.code.get(ip) {
+            ip += 1;
+            match instruction {
+                Instruction::Step => state.step()?,
+                Instruction::Constant(index) => {
+                    stack.push(chunk.constants[index as usize].clone());
+                }
+                Instruction::Load(slot) => match &slots[slot as usize] {
+                    Some(value) => stack.push(value.clone()),
+                    None => return Err(undefined(&chunk.slots[slot as usize])),
+                },
+                Instruction::Store(slot) => slots[slot as usize] = Some(pop(&mut stack)),
+                Instruction::Update { slot, op } => {
+                    let value = pop(&mut stack);
+                    let current = slots[slot as usize]
+                        .as_ref()
+                        .ok_or_else(|| undefined(&chunk.slots[slot as usize]))?;
+                    slots[slot as usize] = Some(binary_op(op.as_str(), current, &value)?);
+                }
+                Instruction::StoreIndex { slot, op } => {
+                    let index = pop(&mut stack);
+                    let value = pop(&mut stack);
+                    let name = &chunk.slots[slot as usize];
+                    match &mut slots[slot as usize] {
+                        Some(Data::Array(array)) => {
+                            let position = match &index {
+                                Data::Number(n) => n.to_usize(),
+                                _ => None,
+                            };
+                            let stored = match position {
+                                Some(i) => state.store_element(array, i, |element| {
+                                    *element = match op {
+                                        Some(op) => binary_op(op.as_str(), element, &value)?,
+                                        None => value,
+                                    };
+                                    Ok(())
+                                })?,
+                                None => false,
+                            };
+                            if !stored {
+                                return Err(RuntimeError::IndexOutOfBounds {
+                                    message: format!(
+                                        "Error: index out of bounds: {}[{:?}]",
+                                        name, index
+                                    ),
+                                }
+                                .into());
+                            }
+                        }
+                        Some(other) => {
+                            return Err(RuntimeError::WrongType {
+                                message: format!("Error: cannot index into {}", other.data_type()),
+                            }
+                            .into())
+                        }
+                        None => return Err(undefined(name)),
+                    }
+                }
+                Instruction::Binary(op) => {
+                    let right = pop(&mut stack);
+                    let left = pop(&mut stack);
+                    stack.push(binary_op(op.as_str(), &left, &right)?);
+                }
+                Instruction::MakeArray(count) => {
+                    let array = Data::from(pop_many(&mut stack, count));
+                    limits.check_size(&array)?;
+                    stack.push(array);
+                }
+                Instruction::Interpolate(count) => {
+                    let mut string = String::new();
+                    for part in pop_many(&mut stack, count) {
+                        string += &part.as_string();
+                    }
+                    let string = Data::String(string.into());
+                    limits.check_size(&string)?;
+                    stack.push(string);
+                }
+                Instruction::MakeRange { inclusive, step } => {
+                    let range = pop_range(&mut stack, inclusive, step)?;
+                    stack.push(limits.collect(range.map(Data::Number))?);
+                }
+                Instruction::Index => {
+                    let index = pop(&mut stack);
+                    let array = pop(&mut stack);
+                    stack.push(index_array(array, index)?);
+                }
+                Instruction::Slice { from, to } => {
+                    let to = to.then(|| pop(&mut stack));
+                    let from = from.then(|| pop(&mut stack));
+                    let array = pop(&mut stack);
+                    stack.push(slice_array(array, from, to)?);
+                }
+                Instruction::Call { name, args } => {
+                    let args = pop_many(&mut stack, args);
+                    let result = self.invoke(&chunk.names[name as usize], args)?;
+                    stack.push(result);
+                }
+                Instruction::Pop => {
+                    pop(&mut stack);
+                }
+                Instruction::Jump(to) => ip = to as usize,
+                Instruction::JumpUnlessTrue(to) => match pop(&mut stack) {
+                    Data::Bool(true) => {}
+                    Data::Bool(false) => ip = to as usize,
+                    other => {
+                        return Err(RuntimeError::WrongType {
+                            message: format!("If condition must be a Bool, got {:?}", other),
+                        }
+                        .into())
+                    }
+                },
+                Instruction::IterRange { inclusive, step } => {
+                    loops.push(Iteration::Range(pop_range(&mut stack, inclusive, step)?));
+                }
+                Instruction::IterArray => match pop(&mut stack) {
+                    Data::Array(array) => loops.push(Iteration::Array(array, 0)),
+                    other => {
+                        return Err(RuntimeError::WrongType {
+                            message: format!(
+                                "For loop collection must be an array, got {:?}",
+                                other
+                            ),
+                        }
+                        .into())
+                    }
+                },
+                Instruction::IterNext { slot, exit } => {
+                    let iteration = loops.last_mut().expect("bytecode left no loop to iterate");
+                    match iteration.next() {
+                        Some(item) => slots[slot as usize] = Some(item),
+                        None => {
+                            loops.pop();
+                            ip = exit as usize;
+                        }
+                    }
+                }
+                Instruction::EnterScope => scopes.push(self.functions.len()),
+                Instruction::ExitScope => {
+                    let visible = scopes
+                        .pop()
+                        .expect("bytecode left a scope it never entered");
+                    self.functions.truncate(visible);
+                }
+                Instruction::DefineFunction(index) => {
+                    let function = &chunk.functions[index as usize];
+                    let id = &*function.name;
+                    if self.function(id).is_some()
+                        | self.program.natives.contains(id)
+                        | (id == "return")
+                    {
+                        return Err(anyhow!("Function `{}` already exist!", id));
+                    }
+                    self.functions.push(function.clone());
+                }
+                Instruction::Return => return Ok(Some(pop(&mut stack))),
+                Instruction::Fail(message) => {
+                    return Err(anyhow!("{}", chunk.names[message as usize]))
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Error for reading variable `name` before anything was stored in it
+fn undefined(name: &str) -> anyhow::Error {
+    RuntimeError::UndefinedVariable {
+        name: name.to_string(),
+    }
+    .into()
+}
@@ -0,0 +1,47 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Lets another thread stop a running [`Program`](super::Program). The program checks the
+/// token between statements and loop iterations and stops with
+/// [`RuntimeError::Cancelled`](super::RuntimeError::Cancelled); whatever it printed so far
+/// stays printed.
+///
+/// ```rust
+/// # use teolang::program::{CancelToken, Program, RuntimeError};
+/// let token = CancelToken::new();
+/// let mut program = Program::builder()
+///     .source("x = 0; for i <- 0..1000000000 { x += 1; }")
+///     .cancel_token(token.clone())
+///     .build()?;
+/// std::thread::spawn(move || {
+///     std::thread::sleep(std::time::Duration::from_millis(50));
+///     token.cancel();
+/// });
+/// let error = program.run().err().unwrap();
+/// assert_eq!(error.downcast_ref(), Some(&RuntimeError::Cancelled));
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// Asks every program holding this token to stop
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Clears the request, so the program can be run again
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::Relaxed);
+    }
+}
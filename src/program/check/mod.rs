@@ -0,0 +1,392 @@
+//! Finds mistakes in a program before it runs: variables and functions that are not defined,
+//! calls with the wrong number of arguments, functions defined twice, variables that are
+//! never used, code after `return`, and values of the wrong type (see [`types`]).
+//!
+//! ```rust
+//! # use teolang::program::check::{check, Severity, Typing};
+//! # use teolang::program::{native::NativeRegistry, parser::Ast};
+//! let ast = Ast::parse_code("def double(n: Number) {\n  return(n * 2);\n}\nx = double(\"3\");")?;
+//! let diagnostics = check(&ast, &NativeRegistry::standard(), Typing::Gradual);
+//! assert_eq!(diagnostics[0].severity, Severity::Error);
+//! assert_eq!(diagnostics[0].line, 4);
+//! assert_eq!(
+//!     diagnostics[0].message,
+//!     "`double` expects a Number for `n`, got a String"
+//! );
+//! assert_eq!(diagnostics[1].message, "Variable `x` is never used");
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use super::native::NativeRegistry;
+use super::parser::{Ast, Statement};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+pub mod types;
+
+pub use types::Typing;
+
+/// How bad a [`Diagnostic`] is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The program runs, but probably not the way it was meant to
+    Warning,
+    /// The program fails when it gets there
+    Error,
+}
+
+/// A mistake found by [`check`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Line (starting at 1) of the statement with the mistake
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Checks a whole program (an [`Ast::Block`]) that can call the functions of `natives`.
+/// Diagnostics are sorted by line, errors first.
+///
+/// Functions defined in an `if` or a `for` can only be called from inside it:
+/// ```rust
+/// # use teolang::program::check::{check, Typing};
+/// # use teolang::program::{native::NativeRegistry, parser::Ast};
+/// let code = "if (true) {\n  def one() {\n    return(1);\n  }\n  return(one());\n}\nreturn(one());";
+/// let diagnostics = check(&Ast::parse_code(code)?, &NativeRegistry::new(), Typing::Gradual);
+/// let errors: Vec<_> = diagnostics.iter().map(ToString::to_string).collect();
+/// assert_eq!(errors, ["line 7: Function `one` is not defined"]);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn check(program: &Ast, natives: &NativeRegistry, typing: Typing) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        natives,
+        blocks: Vec::new(),
+        running_from: 0,
+        diagnostics: Vec::new(),
+        line: 1,
+    };
+    let mut scope = Scope::new();
+    checker.block(program, &mut scope);
+    checker.end_scope(scope);
+    checker
+        .diagnostics
+        .extend(types::check(program, natives, typing));
+    checker
+        .diagnostics
+        .sort_by_key(|d| (d.line, std::cmp::Reverse(d.severity)));
+    checker.diagnostics
+}
+
+struct Checker<'a> {
+    natives: &'a NativeRegistry,
+    /// Functions of the blocks around the code being checked, innermost last
+    blocks: Vec<Block>,
+    /// First of `blocks` that runs along with the code being checked. The ones before are
+    /// around the function it is in, which only runs once they defined all of their functions.
+    running_from: usize,
+    diagnostics: Vec<Diagnostic>,
+    /// Line of the statement being checked
+    line: usize,
+}
+
+/// Variables of the program's top level or of a function body
+struct Scope {
+    /// Line each variable is first assigned on, and whether it is ever read
+    variables: HashMap<String, (usize, bool)>,
+    /// Variables already reported as not defined
+    undefined: HashSet<String>,
+}
+
+impl Scope {
+    fn new() -> Scope {
+        Scope {
+            variables: HashMap::new(),
+            undefined: HashSet::new(),
+        }
+    }
+}
+
+/// Functions defined by a block. They can only be called from inside it, like when running
+/// (an `if` or a `for` forgets the functions it defined once it is done).
+#[derive(Default)]
+struct Block {
+    /// Parameters of every `def` of the block, by function name
+    functions: HashMap<String, Vec<(String, String)>>,
+    /// Functions whose `def` was checked already, which the block runs from top to bottom
+    defined: HashSet<String>,
+}
+
+impl Checker<'_> {
+    fn report(&mut self, severity: Severity, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            line: self.line,
+            message,
+        });
+    }
+
+    fn block(&mut self, block: &Ast, scope: &mut Scope) {
+        let single;
+        let statements = match block {
+            Ast::Block(statements) => statements.as_slice(),
+            statement => {
+                single = [Statement::from(statement.clone())];
+                &single
+            }
+        };
+        let mut functions = Block::default();
+        for statement in statements {
+            if let Ast::FunctionDefinition { id, params, .. } = &statement.ast {
+                functions
+                    .functions
+                    .entry(id.clone())
+                    .or_insert_with(|| params.clone());
+            }
+        }
+        self.blocks.push(functions);
+        self.statements(statements, scope);
+        self.blocks.pop();
+    }
+
+    fn statements(&mut self, statements: &[Statement], scope: &mut Scope) {
+        let mut returned = false;
+        let mut functions = HashSet::new();
+        for Statement {
+            line,
+            ast: statement,
+        } in statements
+        {
+            if let Ast::Comment(_) = statement {
+                continue;
+            }
+            if *line != 0 {
+                self.line = *line;
+            }
+            if returned {
+                self.report(
+                    Severity::Warning,
+                    "Code after `return` never runs".to_string(),
+                );
+                break;
+            }
+            if let Ast::FunctionDefinition { id, .. } = statement {
+                if id == "return" || self.natives.contains(id) {
+                    self.report(
+                        Severity::Error,
+                        format!("`{}` is a standard function, pick another name", id),
+                    );
+                } else if !functions.insert(id.clone()) {
+                    self.report(
+                        Severity::Error,
+                        format!("Function `{}` is defined twice", id),
+                    );
+                }
+            }
+            self.statement(statement, scope);
+            returned = statement.is_return();
+        }
+    }
+
+    fn statement(&mut self, statement: &Ast, scope: &mut Scope) {
+        match statement {
+            Ast::Set { id, op, expr } => {
+                self.expression(expr, scope);
+                match id.as_ref() {
+                    Ast::Identifier(name) => {
+                        if op.is_some() {
+                            self.read(name, scope);
+                        }
+                        let line = self.line;
+                        scope.variables.entry(name.clone()).or_insert((line, false));
+                    }
+                    target => self.expression(target, scope),
+                }
+            }
+            Ast::If { condition, block } => {
+                self.expression(condition, scope);
+                self.block(block, scope);
+            }
+            Ast::ForLoop {
+                element,
+                elements,
+                block,
+            } => {
+                self.expression(elements, scope);
+                // Loop variables are often only there to count, so they count as used
+                if let Ast::Identifier(name) = element.as_ref() {
+                    scope.variables.insert(name.clone(), (self.line, true));
+                }
+                self.block(block, scope);
+            }
+            Ast::FunctionDefinition { id, params, body } => {
+                if let Some(block) = self.blocks.last_mut() {
+                    block.defined.insert(id.clone());
+                }
+                let mut body_scope = Scope::new();
+                for (name, _) in params {
+                    body_scope.variables.insert(name.clone(), (self.line, true));
+                }
+                let line = self.line;
+                let running_from = std::mem::replace(&mut self.running_from, self.blocks.len());
+                self.block(body, &mut body_scope);
+                self.running_from = running_from;
+                self.end_scope(body_scope);
+                self.line = line;
+            }
+            Ast::FunctionCall { id, args } if id == "return" => {
+                if args.len() != 1 {
+                    self.report(
+                        Severity::Error,
+                        format!("`return` takes 1 argument, got {}", args.len()),
+                    );
+                }
+                args.iter().for_each(|arg| self.expression(arg, scope));
+            }
+            expr => self.expression(expr, scope),
+        }
+    }
+
+    fn expression(&mut self, expr: &Ast, scope: &mut Scope) {
+        match expr {
+            Ast::Identifier(name) => self.read(name, scope),
+            Ast::FunctionCall { id, args } => {
+                args.iter().for_each(|arg| self.expression(arg, scope));
+                self.call(id, args);
+            }
+            Ast::Interpolated(parts) | Ast::Array(parts) => {
+                parts.iter().for_each(|part| self.expression(part, scope))
+            }
+            Ast::BinaryOp { left, right, .. } => {
+                self.expression(left, scope);
+                self.expression(right, scope);
+            }
+            Ast::ArrayAccess { expr, whereto } => {
+                self.expression(expr, scope);
+                self.expression(whereto, scope);
+            }
+            Ast::ArrayCall { id, k } => {
+                self.read(id, scope);
+                self.expression(k, scope);
+            }
+            Ast::AstSlice { from, to } => {
+                for bound in [from, to].into_iter().flatten() {
+                    self.expression(bound, scope);
+                }
+            }
+            Ast::Range {
+                start, end, step, ..
+            } => {
+                self.expression(start, scope);
+                self.expression(end, scope);
+                if let Some(step) = step {
+                    self.expression(step, scope);
+                }
+            }
+            Ast::Fac(expr) => self.expression(expr, scope),
+            _ => {}
+        }
+    }
+
+    fn read(&mut self, name: &str, scope: &mut Scope) {
+        match scope.variables.get_mut(name) {
+            Some((_, used)) => *used = true,
+            None => {
+                if scope.undefined.insert(name.to_string()) {
+                    self.report(
+                        Severity::Error,
+                        format!("Variable `{}` is not defined", name),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Parameters of the function `id` the code being checked would call, and whether its
+    /// `def` surely ran by then
+    fn function(&self, id: &str) -> Option<(Vec<(String, String)>, bool)> {
+        let mut found = None;
+        for (i, block) in self.blocks.iter().enumerate().rev() {
+            if let Some(params) = block.functions.get(id) {
+                if i < self.running_from || block.defined.contains(id) {
+                    return Some((params.clone(), true));
+                }
+                found.get_or_insert((params.clone(), false));
+            }
+        }
+        found
+    }
+
+    fn call(&mut self, id: &str, args: &[Ast]) {
+        if id == "return" {
+            self.report(
+                Severity::Error,
+                "`return` can only be used as a statement".to_string(),
+            );
+            return;
+        }
+        if let Some(native) = self.natives.get(id).cloned() {
+            let arity = native.arity();
+            if !arity.accepts(args.len()) {
+                let expected = match arity.max {
+                    Some(max) if max == arity.min => max.to_string(),
+                    Some(max) => format!("{} to {}", arity.min, max),
+                    None => format!("at least {}", arity.min),
+                };
+                self.report(
+                    Severity::Error,
+                    format!(
+                        "`{}` takes {} argument(s), got {}",
+                        id,
+                        expected,
+                        args.len()
+                    ),
+                );
+            }
+            return;
+        }
+        let Some((params, defined)) = self.function(id) else {
+            self.report(Severity::Error, format!("Function `{}` is not defined", id));
+            return;
+        };
+        if !defined {
+            self.report(
+                Severity::Error,
+                format!("Function `{}` is called before it is defined", id),
+            );
+        }
+        if params.len() != args.len() {
+            self.report(
+                Severity::Error,
+                format!(
+                    "`{}` takes {} argument(s), got {}",
+                    id,
+                    params.len(),
+                    args.len()
+                ),
+            );
+        }
+    }
+
+    /// Reports the variables of `scope` that were never read
+    fn end_scope(&mut self, scope: Scope) {
+        let mut unused: Vec<_> = scope
+            .variables
+            .into_iter()
+            .filter(|(_, (_, used))| !used)
+            .collect();
+        unused.sort_by_key(|(_, (line, _))| *line);
+        for (name, (line, _)) in unused {
+            self.diagnostics.push(Diagnostic {
+                severity: Severity::Warning,
+                line,
+                message: format!("Variable `{}` is never used", name),
+            });
+        }
+    }
+}
@@ -0,0 +1,479 @@
+//! Type inference for [`check`](super::check). Types start from literals, parameter
+//! annotations (`n: Number`) and what standard functions return, then follow variables and
+//! function calls through the program. The type of what a function returns is inferred from
+//! its `return`s.
+//!
+//! ```rust
+//! # use teolang::program::check::{check, Typing};
+//! # use teolang::program::{native::NativeRegistry, parser::Ast};
+//! let code = "def double(n: Number) {\n  return(n * 2);\n}\ns = \"3\";\nprint(double(s));\nprint(s < 2);";
+//! let diagnostics = check(&Ast::parse_code(code)?, &NativeRegistry::standard(), Typing::Gradual);
+//! let messages: Vec<_> = diagnostics.iter().map(ToString::to_string).collect();
+//! assert_eq!(
+//!     messages,
+//!     [
+//!         "line 5: `double` expects a Number for `n`, got a String",
+//!         "line 6: Operator < needs numbers, got a String",
+//!     ]
+//! );
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! A call is checked against the `def` it calls from where it is, so functions of the same
+//! name in different blocks keep their own types:
+//! ```rust
+//! # use teolang::program::check::{check, Typing};
+//! # use teolang::program::{native::NativeRegistry, parser::Ast};
+//! let code = "if (true) {\n  def f(s: String) {\n    return(s);\n  }\n  print(f(\"a\"));\n}\ndef f(n: Number) {\n  return(n * 2);\n}\nprint(f(2) + 1);\nprint(f(\"b\"));";
+//! let diagnostics = check(&Ast::parse_code(code)?, &NativeRegistry::standard(), Typing::Gradual);
+//! let messages: Vec<_> = diagnostics.iter().map(ToString::to_string).collect();
+//! assert_eq!(messages, ["line 11: `f` expects a Number for `n`, got a String"]);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! Values whose type cannot be known before running, like array elements, are accepted
+//! anywhere ([`Typing::Gradual`]) unless the check is [`Typing::Strict`].
+
+use super::{Diagnostic, Severity};
+use crate::program::native::NativeRegistry;
+use crate::program::parser::{Ast, Statement};
+use crate::program::DataType;
+use std::collections::{HashMap, HashSet};
+
+/// How [`check`](super::check) treats values whose type cannot be inferred
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Typing {
+    /// They are accepted anywhere, only values of a known, wrong type are reported
+    #[default]
+    Gradual,
+    /// They are reported wherever a type is needed
+    Strict,
+}
+
+/// Type of each variable, [`DataType::Any`] if unknown
+type Scope = HashMap<String, DataType>;
+
+pub(super) fn check(program: &Ast, natives: &NativeRegistry, typing: Typing) -> Vec<Diagnostic> {
+    let mut typer = Typer {
+        natives,
+        typing,
+        blocks: Vec::new(),
+        returns: HashMap::new(),
+        inferring: HashSet::new(),
+        return_types: Vec::new(),
+        diagnostics: Vec::new(),
+        line: 1,
+        quiet: 0,
+    };
+    typer.block(program, &mut Scope::new());
+    typer.diagnostics
+}
+
+struct Typer<'a> {
+    natives: &'a NativeRegistry,
+    typing: Typing,
+    /// The `def`s of each block being looked at, outermost first, by function name. Like in
+    /// [`check`](super::check), a function can only be called from inside its block.
+    blocks: Vec<HashMap<&'a str, &'a Ast>>,
+    /// Inferred return type of the functions seen so far, by `def`
+    returns: HashMap<*const Ast, DataType>,
+    /// Functions whose return type is being inferred, to stop at recursive calls
+    inferring: HashSet<*const Ast>,
+    /// Types of the `return`s of each function being inferred
+    return_types: Vec<Vec<DataType>>,
+    diagnostics: Vec<Diagnostic>,
+    line: usize,
+    /// Nothing is reported while above 0, for code that is only looked at to infer types
+    quiet: usize,
+}
+
+impl<'a> Typer<'a> {
+    fn report(&mut self, message: String) {
+        if self.quiet == 0 {
+            self.diagnostics.push(Diagnostic {
+                severity: Severity::Error,
+                line: self.line,
+                message,
+            });
+        }
+    }
+
+    /// Reports `expr` of type `found` where one of `needed` is needed. `what` starts the
+    /// message, like "An `if` condition must be".
+    fn expect(&mut self, found: DataType, needed: &[DataType], what: &str, expr: &Ast) {
+        if needed.contains(&found) || needed.contains(&DataType::Any) {
+            return;
+        }
+        let needed = needed
+            .iter()
+            .map(|t| a(*t))
+            .collect::<Vec<_>>()
+            .join(" or ");
+        if found != DataType::Any {
+            self.report(format!("{} {}, got {}", what, needed, a(found)));
+        } else if self.typing == Typing::Strict {
+            self.report(format!(
+                "Type of `{}` is not known, {} {}",
+                expr,
+                what.to_lowercase(),
+                needed
+            ));
+        }
+    }
+
+    fn block(&mut self, block: &'a Ast, scope: &mut Scope) {
+        let Ast::Block(statements) = block else {
+            self.statement(block, scope);
+            return;
+        };
+        let mut functions = HashMap::new();
+        for statement in statements {
+            if let Ast::FunctionDefinition { id, .. } = &statement.ast {
+                functions.entry(id.as_str()).or_insert(&statement.ast);
+            }
+        }
+        self.blocks.push(functions);
+        for Statement { line, ast } in statements {
+            if let Ast::Comment(_) = ast {
+                continue;
+            }
+            if *line != 0 {
+                self.line = *line;
+            }
+            self.statement(ast, scope);
+            // What comes after never runs
+            if ast.is_return() {
+                break;
+            }
+        }
+        self.blocks.pop();
+    }
+
+    fn statement(&mut self, statement: &'a Ast, scope: &mut Scope) {
+        match statement {
+            Ast::Set { id, op, expr } => {
+                let mut dtype = self.expression(expr, scope);
+                match id.as_ref() {
+                    Ast::Identifier(name) => {
+                        if let Some(op) = op {
+                            let current = scope.get(name).copied().unwrap_or(DataType::Any);
+                            self.operand(op, current, id);
+                            self.operand(op, dtype, expr);
+                            dtype = DataType::Number;
+                        }
+                        scope.insert(name.clone(), dtype);
+                    }
+                    Ast::ArrayAccess {
+                        expr: array,
+                        whereto,
+                    } => {
+                        let array_type = self.expression(array, scope);
+                        self.expect(array_type, &[DataType::Array], "Indexing needs", array);
+                        let index = self.expression(whereto, scope);
+                        self.expect(index, &[DataType::Number], "An index must be", whereto);
+                        if let Some(op) = op {
+                            self.operand(op, dtype, expr);
+                        }
+                    }
+                    target => {
+                        self.expression(target, scope);
+                    }
+                }
+            }
+            Ast::If { condition, block } => {
+                let dtype = self.expression(condition, scope);
+                self.expect(
+                    dtype,
+                    &[DataType::Bool],
+                    "An `if` condition must be",
+                    condition,
+                );
+                let before = scope.clone();
+                self.block(block, scope);
+                merge(&before, scope);
+            }
+            Ast::ForLoop {
+                element,
+                elements,
+                block,
+            } => {
+                let item = match elements.as_ref() {
+                    range @ Ast::Range { .. } => {
+                        self.expression(range, scope);
+                        DataType::Number
+                    }
+                    elements => {
+                        let dtype = self.expression(elements, scope);
+                        self.expect(dtype, &[DataType::Array], "`for` goes over", elements);
+                        DataType::Any
+                    }
+                };
+                scope.insert(element.to_string(), item);
+                // The types the body leaves are the ones of its next run, so it is looked at
+                // once to learn them
+                let before = scope.clone();
+                self.quiet += 1;
+                self.block(block, scope);
+                self.quiet -= 1;
+                merge(&before, scope);
+                let before = scope.clone();
+                self.block(block, scope);
+                merge(&before, scope);
+            }
+            Ast::FunctionDefinition { params, body, .. } => {
+                let mut body_scope = parameters(params).into_iter().collect();
+                let line = self.line;
+                self.return_types.push(Vec::new());
+                self.block(body, &mut body_scope);
+                self.return_types.pop();
+                self.line = line;
+            }
+            Ast::FunctionCall { id, args } if id == "return" => {
+                for arg in args {
+                    let dtype = self.expression(arg, scope);
+                    if let Some(returns) = self.return_types.last_mut() {
+                        returns.push(dtype);
+                    }
+                }
+            }
+            expr => {
+                self.expression(expr, scope);
+            }
+        }
+    }
+
+    fn expression(&mut self, expr: &Ast, scope: &mut Scope) -> DataType {
+        match expr {
+            Ast::Int(_) => DataType::Number,
+            Ast::Bool(_) => DataType::Bool,
+            Ast::String(_) => DataType::String,
+            Ast::Interpolated(parts) => {
+                for part in parts {
+                    self.expression(part, scope);
+                }
+                DataType::String
+            }
+            Ast::Array(elements) => {
+                for element in elements {
+                    self.expression(element, scope);
+                }
+                DataType::Array
+            }
+            Ast::Identifier(name) => scope.get(name).copied().unwrap_or(DataType::Any),
+            Ast::BinaryOp { op, left, right } => {
+                let left_type = self.expression(left, scope);
+                self.operand(op, left_type, left);
+                // The right side of `n!` is not used
+                if op != "!" {
+                    let right_type = self.expression(right, scope);
+                    self.operand(op, right_type, right);
+                }
+                match op.as_str() {
+                    "==" | "!=" | "<" | ">" | "<=" | ">=" => DataType::Bool,
+                    _ => DataType::Number,
+                }
+            }
+            Ast::Fac(expr) => {
+                let dtype = self.expression(expr, scope);
+                self.operand("!", dtype, expr);
+                DataType::Number
+            }
+            Ast::Range {
+                start, end, step, ..
+            } => {
+                for bound in [Some(start), Some(end), step.as_ref()]
+                    .into_iter()
+                    .flatten()
+                {
+                    let dtype = self.expression(bound, scope);
+                    self.expect(dtype, &[DataType::Number], "Range bounds must be", bound);
+                }
+                DataType::Array
+            }
+            Ast::ArrayAccess {
+                expr: array,
+                whereto,
+            } => {
+                let dtype = self.expression(array, scope);
+                self.expect(dtype, &[DataType::Array], "Indexing needs", array);
+                match whereto.as_ref() {
+                    Ast::AstSlice { .. } => DataType::Array,
+                    index => {
+                        let dtype = self.expression(index, scope);
+                        self.expect(dtype, &[DataType::Number], "An index must be", index);
+                        DataType::Any
+                    }
+                }
+            }
+            Ast::ArrayCall { id, k } => {
+                let dtype = scope.get(id).copied().unwrap_or(DataType::Any);
+                self.expect(dtype, &[DataType::Array], "Indexing needs", expr);
+                self.expression(k, scope);
+                DataType::Any
+            }
+            Ast::FunctionCall { id, args } => {
+                let types: Vec<DataType> =
+                    args.iter().map(|arg| self.expression(arg, scope)).collect();
+                self.call(id, args, &types)
+            }
+            _ => DataType::Any,
+        }
+    }
+
+    /// Checks an operand of `op`, which only works on numbers (and bools, which count as 0
+    /// and 1)
+    fn operand(&mut self, op: &str, dtype: DataType, expr: &Ast) {
+        match dtype {
+            DataType::Number | DataType::Bool => {}
+            DataType::Any if self.typing == Typing::Gradual => {}
+            DataType::Any => self.report(format!(
+                "Type of `{}` is not known, operator {} needs numbers",
+                expr, op
+            )),
+            other => self.report(format!("Operator {} needs numbers, got {}", op, a(other))),
+        }
+    }
+
+    /// Checks the arguments of a call and returns what the function returns
+    fn call(&mut self, id: &str, args: &[Ast], types: &[DataType]) -> DataType {
+        if let Some(native) = self.natives.get(id).cloned() {
+            let params = native.param_types();
+            for (i, (arg, dtype)) in args.iter().zip(types).enumerate() {
+                if let Some(expected) = params.get(i).or(params.last()) {
+                    let what = format!("`{}` expects", id);
+                    self.argument(
+                        &what,
+                        &format!("argument {}", i + 1),
+                        *expected,
+                        *dtype,
+                        arg,
+                    );
+                }
+            }
+            return native.return_type();
+        }
+        let Some((level, definition)) = self.function(id) else {
+            return DataType::Any;
+        };
+        let Ast::FunctionDefinition { params, .. } = definition else {
+            return DataType::Any;
+        };
+        for ((name, expected), (arg, dtype)) in
+            parameters(params).iter().zip(args.iter().zip(types))
+        {
+            let what = format!("`{}` expects", id);
+            self.argument(&what, &format!("`{}`", name), *expected, *dtype, arg);
+        }
+        self.return_type(level, definition)
+    }
+
+    /// `def` of the function `id` seen from here, with the index of its block
+    fn function(&self, id: &str) -> Option<(usize, &'a Ast)> {
+        self.blocks
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(level, block)| block.get(id).map(|definition| (level, *definition)))
+    }
+
+    fn argument(
+        &mut self,
+        what: &str,
+        param: &str,
+        expected: DataType,
+        found: DataType,
+        arg: &Ast,
+    ) {
+        if expected == DataType::Any || expected == found {
+            return;
+        }
+        if found != DataType::Any {
+            self.report(format!(
+                "{} {} for {}, got {}",
+                what,
+                a(expected),
+                param,
+                a(found)
+            ));
+        } else if self.typing == Typing::Strict {
+            self.report(format!(
+                "Type of `{}` is not known, {} {} for {}",
+                arg,
+                what,
+                a(expected),
+                param
+            ));
+        }
+    }
+
+    /// Infers what the function of `definition`, in the block at `level`, returns from its
+    /// `return`s, [`DataType::Any`] if they differ
+    fn return_type(&mut self, level: usize, definition: &'a Ast) -> DataType {
+        let key = definition as *const Ast;
+        if let Some(dtype) = self.returns.get(&key) {
+            return *dtype;
+        }
+        let Ast::FunctionDefinition { params, body, .. } = definition else {
+            return DataType::Any;
+        };
+        if !self.inferring.insert(key) {
+            return DataType::Any;
+        }
+        // The body sees the functions of where it is defined, not of where it is called
+        let inner = self.blocks.split_off(level + 1);
+        let mut scope = parameters(params).into_iter().collect();
+        let line = self.line;
+        self.quiet += 1;
+        self.return_types.push(Vec::new());
+        self.block(body, &mut scope);
+        let mut returns = self.return_types.pop().unwrap_or_default();
+        self.quiet -= 1;
+        self.line = line;
+        self.blocks.extend(inner);
+        self.inferring.remove(&key);
+        // A function that ends without `return` returns 0
+        let ends_with_return = match &**body {
+            Ast::Block(statements) => statements
+                .iter()
+                .rev()
+                .find(|s| !matches!(s.ast, Ast::Comment(_))),
+            _ => None,
+        }
+        .is_some_and(|s| s.ast.is_return());
+        if !ends_with_return {
+            returns.push(DataType::Number);
+        }
+        let dtype = match returns.split_first() {
+            Some((first, rest)) if rest.iter().all(|t| t == first) => *first,
+            _ => DataType::Any,
+        };
+        self.returns.insert(key, dtype);
+        dtype
+    }
+}
+
+/// Parameters of a `def` with their types, [`DataType::Any`] if not given
+fn parameters(params: &[(String, String)]) -> Vec<(String, DataType)> {
+    params
+        .iter()
+        .map(|(name, dtype)| (name.clone(), dtype.parse().unwrap_or(DataType::Any)))
+        .collect()
+}
+
+/// `dtype` with "a" or "an" before it
+fn a(dtype: DataType) -> String {
+    match dtype {
+        DataType::Array | DataType::Any => format!("an {}", dtype),
+        dtype => format!("a {}", dtype),
+    }
+}
+
+/// Variables after code that may not have run (or may run again) have the type they had
+/// before it if the code did not change it, [`DataType::Any`] if it did
+fn merge(before: &Scope, after: &mut Scope) {
+    for (name, dtype) in after.iter_mut() {
+        if before.get(name).is_some_and(|b| b != dtype) {
+            *dtype = DataType::Any;
+        }
+    }
+}
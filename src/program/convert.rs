@@ -0,0 +1,127 @@
+//! Conversions between [`Data`] and Rust types, for programs embedding Teo
+
+use super::Data;
+use anyhow::{anyhow, Error, Result};
+use rust_decimal::prelude::*;
+use std::rc::Rc;
+
+impl From<Decimal> for Data {
+    fn from(value: Decimal) -> Self {
+        Data::Number(value)
+    }
+}
+
+macro_rules! from_integer {
+    ($($int:ty),+) => {
+        $(
+            impl From<$int> for Data {
+                fn from(value: $int) -> Self {
+                    Data::Number(Decimal::from(value))
+                }
+            }
+        )+
+    };
+}
+
+from_integer!(i32, i64, u32, u64, usize);
+
+impl TryFrom<f64> for Data {
+    type Error = Error;
+
+    fn try_from(value: f64) -> Result<Self> {
+        Decimal::from_f64(value)
+            .map(Data::Number)
+            .ok_or_else(|| anyhow!("{} cannot be stored as a Number", value))
+    }
+}
+
+impl From<bool> for Data {
+    fn from(value: bool) -> Self {
+        Data::Bool(value)
+    }
+}
+
+impl From<String> for Data {
+    fn from(value: String) -> Self {
+        Data::String(value.into())
+    }
+}
+
+impl From<&str> for Data {
+    fn from(value: &str) -> Self {
+        Data::String(value.into())
+    }
+}
+
+impl<T: Into<Data>> From<Vec<T>> for Data {
+    fn from(value: Vec<T>) -> Self {
+        Data::Array(Rc::new(value.into_iter().map(Into::into).collect()))
+    }
+}
+
+impl TryFrom<Data> for Decimal {
+    type Error = Error;
+
+    fn try_from(value: Data) -> Result<Self> {
+        match value {
+            Data::Number(n) => Ok(n),
+            other => Err(anyhow!("Expected a Number, got {:?}", other)),
+        }
+    }
+}
+
+impl TryFrom<Data> for i64 {
+    type Error = Error;
+
+    fn try_from(value: Data) -> Result<Self> {
+        let n = Decimal::try_from(value)?;
+        if !n.is_integer() {
+            return Err(anyhow!("Expected a whole number, got {}", n));
+        }
+        n.to_i64()
+            .ok_or_else(|| anyhow!("{} does not fit in an i64", n))
+    }
+}
+
+impl TryFrom<Data> for f64 {
+    type Error = Error;
+
+    fn try_from(value: Data) -> Result<Self> {
+        let n = Decimal::try_from(value)?;
+        n.to_f64()
+            .ok_or_else(|| anyhow!("{} does not fit in an f64", n))
+    }
+}
+
+impl TryFrom<Data> for bool {
+    type Error = Error;
+
+    fn try_from(value: Data) -> Result<Self> {
+        match value {
+            Data::Bool(b) => Ok(b),
+            other => Err(anyhow!("Expected a Bool, got {:?}", other)),
+        }
+    }
+}
+
+impl TryFrom<Data> for String {
+    type Error = Error;
+
+    fn try_from(value: Data) -> Result<Self> {
+        match value {
+            Data::String(s) => Ok(s.to_string()),
+            other => Err(anyhow!("Expected a String, got {:?}", other)),
+        }
+    }
+}
+
+impl TryFrom<Data> for Vec<Data> {
+    type Error = Error;
+
+    fn try_from(value: Data) -> Result<Self> {
+        match value {
+            Data::Array(a) => Ok(Rc::unwrap_or_clone(a)),
+            other => Err(anyhow!("Expected an Array, got {:?}", other)),
+        }
+    }
+}
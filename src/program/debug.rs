@@ -0,0 +1,269 @@
+//! Hooks for debuggers, which stop a program before each statement to look at where it is
+//! and what its variables hold, and for tracers, which also follow the values it computes.
+//!
+//! ```rust
+//! # use teolang::program::debug::{Debugger, Pause};
+//! # use teolang::program::Program;
+//! # use std::{cell::RefCell, rc::Rc};
+//! /// Writes down where the program went
+//! struct Lines(Rc<RefCell<Vec<(usize, usize)>>>);
+//!
+//! impl Debugger for Lines {
+//!     fn pause(&mut self, pause: &Pause<'_>) -> anyhow::Result<()> {
+//!         self.0.borrow_mut().push((pause.line, pause.stack.len()));
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let lines = Rc::new(RefCell::new(Vec::new()));
+//! let mut program = Program::builder()
+//!     .source("def double(n: Number) {\n  return(n * 2);\n}\nx = double(2);")
+//!     .debugger(Lines(lines.clone()))
+//!     .build()?;
+//! program.run()?;
+//! // Line and number of functions being run
+//! assert_eq!(*lines.borrow(), [(1, 0), (4, 0), (2, 1)]);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use super::{parser::Ast, Data};
+use anyhow::Result;
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+/// Called by a running program before each statement. Only the
+/// [`Engine::TreeWalker`](super::Engine::TreeWalker) knows about statements, so
+/// [`ProgramBuilder::debugger`](super::ProgramBuilder::debugger) picks it.
+pub trait Debugger {
+    /// The program is about to run `pause.statement`; it goes on once this returns. An error
+    /// stops the program with it, [`Stopped`] to stop it without failing.
+    fn pause(&mut self, pause: &Pause<'_>) -> Result<()>;
+
+    /// `expr`, the value an assignment stores, a condition, a loop's collection or a returned
+    /// value of `at.statement`, evaluated to `value`
+    fn evaluated(&mut self, at: &Pause<'_>, expr: &Ast, value: &Data) -> Result<()> {
+        let _ = (at, expr, value);
+        Ok(())
+    }
+
+    /// Variable `name` was set to `value`, by an assignment (for an array element, `value` is
+    /// the whole array) or as the variable of a `for` loop
+    fn assigned(&mut self, at: &Pause<'_>, name: &str, value: &Data) -> Result<()> {
+        let _ = (at, name, value);
+        Ok(())
+    }
+}
+
+/// Error a [`Debugger`] stops the program with when whoever debugs it is done with it, like
+/// the `quit` of `teo debug`. Unlike other errors, `teo` does not report it and exits with 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stopped;
+
+impl fmt::Display for Stopped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Program was stopped by the debugger")
+    }
+}
+
+impl std::error::Error for Stopped {}
+
+/// Where a program stands, see [`Debugger::pause`]
+pub struct Pause<'a> {
+    /// Line (starting at 1) of the statement
+    pub line: usize,
+    pub statement: &'a Ast,
+    /// Functions being run, outermost first. Empty at the top level.
+    pub stack: &'a [Frame],
+    /// Variables of the function being run, or of the top level
+    pub variables: &'a HashMap<String, Data>,
+}
+
+/// A function being run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub function: String,
+    /// Line it was called from
+    pub line: usize,
+}
+
+/// Why a [`Stepper`] stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// At the first statement
+    Entry,
+    Step,
+    Breakpoint,
+}
+
+/// What a [`Stepper`] lets run before stopping again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Up to the next line, in this function or one it calls
+    Step,
+    /// Up to the next line of this function or of its caller
+    Next,
+    /// Up to the caller of this function
+    Out,
+    /// Up to a breakpoint
+    Continue,
+}
+
+/// Decides where a [`Debugger`] stops, from breakpoints and how the last stop said to go on.
+///
+/// ```rust
+/// # use teolang::program::debug::{Debugger, Mode, Pause, Reason, Stepper};
+/// # use teolang::program::Program;
+/// # use std::{cell::RefCell, rc::Rc};
+/// /// Writes down each stop, then goes on the way `modes` say
+/// struct Script {
+///     stepper: Stepper,
+///     modes: Vec<Mode>,
+///     stops: Rc<RefCell<Vec<(usize, Reason)>>>,
+/// }
+///
+/// impl Debugger for Script {
+///     fn pause(&mut self, pause: &Pause<'_>) -> anyhow::Result<()> {
+///         if let Some(reason) = self.stepper.stop(pause) {
+///             self.stops.borrow_mut().push((pause.line, reason));
+///             self.stepper.mode = self.modes.pop().unwrap_or(Mode::Continue);
+///         }
+///         Ok(())
+///     }
+/// }
+///
+/// let code = "def double(n: Number) {\n  m = n * 2;\n  return(m);\n}\nx = double(1);\ny = double(x);\nz = y;";
+/// let run = |mut stepper: Stepper, mut modes: Vec<Mode>| {
+///     modes.reverse();
+///     let stops = Rc::new(RefCell::new(Vec::new()));
+///     let script = Script { stepper, modes, stops: stops.clone() };
+///     Program::builder().source(code).debugger(script).build()?.run()?;
+///     Ok::<_, anyhow::Error>(stops.take())
+/// };
+///
+/// // Step goes into `double`, Next stays out of it and Out leaves it
+/// let modes = vec![Mode::Step, Mode::Step, Mode::Out, Mode::Next];
+/// let stops = run(Stepper::new(Mode::Step), modes)?;
+/// assert_eq!(
+///     stops,
+///     [
+///         (1, Reason::Entry),
+///         (5, Reason::Step),
+///         (2, Reason::Step),
+///         (6, Reason::Step),
+///         (7, Reason::Step),
+///     ]
+/// );
+///
+/// // Continue runs to each breakpoint, once per call for those in a function
+/// let mut stepper = Stepper::new(Mode::Continue);
+/// stepper.breakpoints.extend([3, 7]);
+/// let stops = run(stepper, vec![])?;
+/// assert_eq!(
+///     stops,
+///     [(3, Reason::Breakpoint), (3, Reason::Breakpoint), (7, Reason::Breakpoint)]
+/// );
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Stepper {
+    /// Lines to stop at
+    pub breakpoints: BTreeSet<usize>,
+    /// What to run before the next stop, besides breakpoints
+    pub mode: Mode,
+    /// Line and call depth of the last stop, `None` before the first
+    stopped: Option<(usize, usize)>,
+    /// Line and call depth of the last statement, so a line with several statements hits
+    /// its breakpoint once
+    last: (usize, usize),
+}
+
+impl Stepper {
+    /// Starts by stopping at the first statement, or with `Mode::Continue` at the first
+    /// breakpoint
+    pub fn new(mode: Mode) -> Stepper {
+        Stepper {
+            breakpoints: BTreeSet::new(),
+            mode,
+            stopped: None,
+            last: (0, 0),
+        }
+    }
+
+    /// Whether to stop before the statement of `pause`, and why
+    pub fn stop(&mut self, pause: &Pause<'_>) -> Option<Reason> {
+        let here = (pause.line, pause.stack.len());
+        let new_line = here != self.last;
+        self.last = here;
+        let (line, depth) = self.stopped.unwrap_or((0, 0));
+        let step = match self.mode {
+            Mode::Step => here != (line, depth),
+            Mode::Next => here.1 < depth || (here.1 == depth && here.0 != line),
+            Mode::Out => here.1 < depth,
+            Mode::Continue => false,
+        };
+        let reason = if step && self.stopped.is_none() {
+            Reason::Entry
+        } else if step {
+            Reason::Step
+        } else if new_line && self.breakpoints.contains(&pause.line) {
+            Reason::Breakpoint
+        } else {
+            return None;
+        };
+        self.stopped = Some(here);
+        Some(reason)
+    }
+}
+
+/// A [`Debugger`] and the call stack it is shown, shared by a program and all of its nested
+/// blocks and calls
+pub(crate) struct DebugState {
+    debugger: RefCell<Box<dyn Debugger>>,
+    stack: RefCell<Vec<Frame>>,
+}
+
+impl DebugState {
+    pub(crate) fn new(debugger: Box<dyn Debugger>) -> DebugState {
+        DebugState {
+            debugger: RefCell::new(debugger),
+            stack: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Calls `hook` with the debugger and where the program stands
+    pub(crate) fn hook(
+        &self,
+        line: usize,
+        statement: &Ast,
+        variables: &HashMap<String, Data>,
+        hook: impl FnOnce(&mut dyn Debugger, &Pause<'_>) -> Result<()>,
+    ) -> Result<()> {
+        let pause = Pause {
+            line,
+            statement,
+            stack: &self.stack.borrow(),
+            variables,
+        };
+        hook(self.debugger.borrow_mut().as_mut(), &pause)
+    }
+
+    /// Enters a function called from `line`; it leaves the stack when the guard is dropped
+    pub(crate) fn enter(&self, function: &str, line: usize) -> FrameGuard<'_> {
+        self.stack.borrow_mut().push(Frame {
+            function: function.to_string(),
+            line,
+        });
+        FrameGuard { state: self }
+    }
+}
+
+pub(crate) struct FrameGuard<'a> {
+    state: &'a DebugState,
+}
+
+impl Drop for FrameGuard<'_> {
+    fn drop(&mut self) {
+        self.state.stack.borrow_mut().pop();
+    }
+}
@@ -0,0 +1,98 @@
+use std::fmt;
+use std::time::Duration;
+
+/// Errors a running program stops with: the mistakes a program can make, and going past one
+/// of its [`Limits`](super::Limits) or being cancelled. Use `anyhow::Error::downcast_ref` to
+/// tell them apart from other errors.
+///
+/// ```rust
+/// # use teolang::program::{Program, RuntimeError};
+/// let mut program = Program::builder().source("print(total);").build()?;
+/// let error = program.run().err().unwrap();
+/// assert_eq!(
+///     error.downcast_ref(),
+///     Some(&RuntimeError::UndefinedVariable { name: "total".to_string() })
+/// );
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuntimeError {
+    /// More statements were run than [`Limits::max_steps`](super::Limits::max_steps)
+    StepLimitExceeded { limit: u64 },
+    /// Functions called each other deeper than
+    /// [`Limits::max_call_depth`](super::Limits::max_call_depth)
+    CallDepthExceeded { limit: usize },
+    /// An array or string grew past [`Limits::max_value_size`](super::Limits::max_value_size)
+    MemoryLimitExceeded { limit: usize },
+    /// The program ran longer than [`Limits::timeout`](super::Limits::timeout)
+    Timeout { limit: Duration },
+    /// The program's [`CancelToken`](super::CancelToken) was cancelled
+    Cancelled,
+    /// A variable was read before anything was stored in it
+    UndefinedVariable { name: String },
+    /// A function was called that is neither defined nor standard
+    UndefinedFunction { name: String },
+    /// A value of the wrong type was used, like adding a String, indexing into a Number or
+    /// testing an Array in an `if`
+    WrongType { message: String },
+    /// A function was given too many or too few arguments, or one of the wrong type
+    WrongArguments { message: String },
+    /// A number was divided by zero
+    DivisionByZero,
+    /// An index or slice went past the end of an array
+    IndexOutOfBounds { message: String },
+}
+
+impl RuntimeError {
+    /// Code `teo` exits with when a program stops with this error, one per kind (71 to 81).
+    /// Other runtime errors exit with 70.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RuntimeError::StepLimitExceeded { .. } => 71,
+            RuntimeError::CallDepthExceeded { .. } => 72,
+            RuntimeError::MemoryLimitExceeded { .. } => 73,
+            RuntimeError::Timeout { .. } => 74,
+            RuntimeError::Cancelled => 75,
+            RuntimeError::UndefinedVariable { .. } => 76,
+            RuntimeError::UndefinedFunction { .. } => 77,
+            RuntimeError::WrongType { .. } => 78,
+            RuntimeError::WrongArguments { .. } => 79,
+            RuntimeError::DivisionByZero => 80,
+            RuntimeError::IndexOutOfBounds { .. } => 81,
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::StepLimitExceeded { limit } => {
+                write!(f, "Program ran more than {} steps", limit)
+            }
+            RuntimeError::CallDepthExceeded { limit } => write!(
+                f,
+                "Functions called each other more than {} levels deep (missing base case?)",
+                limit
+            ),
+            RuntimeError::MemoryLimitExceeded { limit } => {
+                write!(f, "Array or string grew larger than {}", limit)
+            }
+            RuntimeError::Timeout { limit } => {
+                write!(f, "Program ran longer than {:?}", limit)
+            }
+            RuntimeError::Cancelled => write!(f, "Program was cancelled"),
+            RuntimeError::UndefinedVariable { name } => {
+                write!(f, "Error: variable not found: {}", name)
+            }
+            RuntimeError::UndefinedFunction { name } => {
+                write!(f, "Function `{}` is not defined!", name)
+            }
+            RuntimeError::WrongType { message }
+            | RuntimeError::WrongArguments { message }
+            | RuntimeError::IndexOutOfBounds { message } => write!(f, "{}", message),
+            RuntimeError::DivisionByZero => write!(f, "Error: division by zero"),
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
@@ -0,0 +1,290 @@
+//! Writes an [`Ast`] back as Teo code in canonical style: four spaces of indentation, one
+//! statement per line, spaces around binary operators, `{` on the line that opens the block
+//! and a blank line around function definitions. Parentheses are only written where the
+//! precedence of the operators needs them, and comments are kept. A comment after a
+//! statement stays on its line, unless the statement has a block: then, like any other
+//! comment, it goes on a line of its own.
+//!
+//! Parsing the formatted code gives back the same [`Ast`] but for the lines of its
+//! statements, so formatting never changes what a program does:
+//! ```rust
+//! # use teolang::program::{format::format, parser::Ast};
+//! let ast = Ast::parse_code("def sq(n:Number){return(n*n);}\nx=(1+2)*sq(3);// 27\nprint(\"x is {x}\");")?;
+//! let code = format(&ast);
+//! assert_eq!(
+//!     code,
+//!     "def sq(n: Number) {\n    return(n * n);\n}\n\nx = (1 + 2) * sq(3); // 27\nprint(\"x is {x}\");\n"
+//! );
+//! assert_eq!(Ast::parse_code(&code)?.without_lines(), ast.without_lines());
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use super::parser::{Ast, Statement};
+
+const INDENT: &str = "    ";
+
+/// Formats a whole program (an [`Ast::Block`]), ending with a newline. Other nodes are
+/// formatted as a single statement.
+pub fn format(program: &Ast) -> String {
+    let mut formatter = Formatter::default();
+    match program {
+        Ast::Block(statements) => formatter.statements(statements),
+        statement => formatter.statement(statement),
+    }
+    formatter.out
+}
+
+/// Formats an expression on one line
+pub fn expression(expr: &Ast) -> String {
+    match expr {
+        Ast::Int(n) => n.to_string(),
+        Ast::Bool(b) => b.to_string(),
+        Ast::Identifier(id) => id.clone(),
+        Ast::String(s) => format!("\"{}\"", escape(s)),
+        Ast::Interpolated(parts) => {
+            let parts: String = parts
+                .iter()
+                .map(|part| match part {
+                    Ast::String(s) => escape(s),
+                    expr => format!("{{{}}}", expression(expr)),
+                })
+                .collect();
+            format!("\"{}\"", parts)
+        }
+        Ast::Array(elements) => format!("[{}]", list(elements)),
+        Ast::FunctionCall { id, args } => format!("{}({})", id, list(args)),
+        Ast::ArrayCall { id, k } => format!("{}[{}]", id, expression(k)),
+        Ast::ArrayAccess { expr, whereto } => {
+            // Only these can be indexed without parentheses
+            let expr = match &**expr {
+                expr @ (Ast::Identifier(_) | Ast::FunctionCall { .. } | Ast::Array(_)) => {
+                    expression(expr)
+                }
+                expr => format!("({})", expression(expr)),
+            };
+            format!("{}[{}]", expr, index(whereto))
+        }
+        Ast::AstSlice { .. } => index(expr),
+        Ast::Fac(expr) => format!("{}!", operand(expr, FACTORIAL)),
+        Ast::BinaryOp { op, left, .. } if op == "!" => format!("{}!", operand(left, FACTORIAL)),
+        Ast::BinaryOp { op, left, right } if is_negation(op, left) => {
+            format!("-{}", operand(right, NEGATION))
+        }
+        Ast::BinaryOp { op, left, right } => {
+            let precedence = precedence(expr);
+            // `^` groups to the right, every other operator to the left
+            let (left, right) = if op == "^" {
+                (operand(left, precedence + 1), operand(right, precedence))
+            } else {
+                (operand(left, precedence), operand(right, precedence + 1))
+            };
+            format!("{} {} {}", left, op, right)
+        }
+        Ast::Range {
+            start,
+            end,
+            step,
+            inclusive,
+        } => {
+            let mut range = format!(
+                "{}{}{}",
+                operand(start, RANGE + 1),
+                if *inclusive { "..=" } else { ".." },
+                operand(end, RANGE + 1)
+            );
+            if let Some(step) = step {
+                range += &format!(" step {}", operand(step, RANGE + 1));
+            }
+            range
+        }
+        Ast::Comment(text) => format!("//{}", text),
+        Ast::Block(_)
+        | Ast::Set { .. }
+        | Ast::If { .. }
+        | Ast::FunctionDefinition { .. }
+        | Ast::ForLoop { .. } => format(expr).trim_end().to_string(),
+    }
+}
+
+const STEP: u8 = 1;
+const RANGE: u8 = 2;
+const COMPARISON: u8 = 3;
+const SUM: u8 = 4;
+const PRODUCT: u8 = 5;
+const POWER: u8 = 6;
+const NEGATION: u8 = 7;
+const FACTORIAL: u8 = 8;
+const ATOM: u8 = 9;
+
+/// How tightly an expression binds, the same order as the parser's
+fn precedence(expr: &Ast) -> u8 {
+    match expr {
+        Ast::Range { step: Some(_), .. } => STEP,
+        Ast::Range { .. } => RANGE,
+        Ast::Fac(_) => FACTORIAL,
+        Ast::BinaryOp { op, left, .. } => match op.as_str() {
+            "!" => FACTORIAL,
+            "^" => POWER,
+            "*" | "/" => PRODUCT,
+            "-" if is_negation(op, left) => NEGATION,
+            "+" | "-" => SUM,
+            _ => COMPARISON,
+        },
+        _ => ATOM,
+    }
+}
+
+/// `-x` is parsed as `0 - x`
+fn is_negation(op: &str, left: &Ast) -> bool {
+    op == "-" && matches!(left, Ast::Int(n) if n.is_zero())
+}
+
+/// Formats `expr`, in parentheses if it binds less tightly than `min`
+fn operand(expr: &Ast, min: u8) -> String {
+    if precedence(expr) < min {
+        format!("({})", expression(expr))
+    } else {
+        expression(expr)
+    }
+}
+
+/// What goes between the brackets of `x[...]`
+fn index(whereto: &Ast) -> String {
+    match whereto {
+        Ast::AstSlice { from, to } => {
+            let bound = |bound: &Option<Box<Ast>>| bound.as_deref().map(expression);
+            match (bound(from), bound(to)) {
+                (Some(from), Some(to)) => format!("{}..{}", from, to),
+                (from, to) => format!("{}:{}", from.unwrap_or_default(), to.unwrap_or_default()),
+            }
+        }
+        // `x[1..2]` would be read as a slice
+        range @ Ast::Range { .. } => format!("({})", expression(range)),
+        expr => expression(expr),
+    }
+}
+
+fn list(elements: &[Ast]) -> String {
+    elements
+        .iter()
+        .map(expression)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Escapes `s` to go between the quotes of a string literal
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped += "\\\"",
+            '\\' => escaped += "\\\\",
+            '\n' => escaped += "\\n",
+            '\r' => escaped += "\\r",
+            '\t' => escaped += "\\t",
+            '\0' => escaped += "\\0",
+            '{' => escaped += "{{",
+            '}' => escaped += "}}",
+            c if c.is_control() => escaped += &format!("\\u{{{:x}}}", c as u32),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[derive(Default)]
+struct Formatter {
+    out: String,
+    depth: usize,
+}
+
+impl Formatter {
+    fn line(&mut self, line: &str) {
+        for _ in 0..self.depth {
+            self.out += INDENT;
+        }
+        self.out += line;
+        self.out.push('\n');
+    }
+
+    fn statements(&mut self, block: &[Statement]) {
+        let statements: Vec<&Ast> = block.iter().map(|s| &s.ast).collect();
+        for (i, statement) in statements.iter().enumerate() {
+            // Statements with a block take more than one line once formatted
+            let trailing = i > 0
+                && block[i].line != 0
+                && block[i].line == block[i - 1].line
+                && !matches!(
+                    statements[i - 1],
+                    Ast::Comment(_)
+                        | Ast::If { .. }
+                        | Ast::ForLoop { .. }
+                        | Ast::FunctionDefinition { .. }
+                );
+            if let (Ast::Comment(_), true) = (statement, trailing) {
+                self.out.pop();
+                self.out += &format!(" {}\n", expression(statement));
+                continue;
+            }
+            // A function and the comments above it are set apart by blank lines
+            let starts_function = statements[i..]
+                .iter()
+                .find(|s| !matches!(s, Ast::Comment(_)))
+                .is_some_and(|s| matches!(s, Ast::FunctionDefinition { .. }));
+            let blank_before = i > 0
+                && match statements[i - 1] {
+                    Ast::FunctionDefinition { .. } => true,
+                    Ast::Comment(_) => false,
+                    _ => starts_function,
+                };
+            if blank_before {
+                self.out.push('\n');
+            }
+            self.statement(statement);
+        }
+    }
+
+    /// Writes `header {`, the statements of `block` one level deeper, then `}`
+    fn block(&mut self, header: &str, block: &Ast) {
+        self.line(&format!("{} {{", header));
+        self.depth += 1;
+        match block {
+            Ast::Block(statements) => self.statements(statements),
+            statement => self.statement(statement),
+        }
+        self.depth -= 1;
+        self.line("}");
+    }
+
+    fn statement(&mut self, statement: &Ast) {
+        match statement {
+            Ast::Block(statements) => self.statements(statements),
+            Ast::Comment(_) => self.line(&expression(statement)),
+            Ast::Set { id, op, expr } => self.line(&format!(
+                "{} {}= {};",
+                expression(id),
+                op.as_deref().unwrap_or_default(),
+                expression(expr)
+            )),
+            Ast::If { condition, block } => {
+                self.block(&format!("if ({})", expression(condition)), block)
+            }
+            Ast::FunctionDefinition { id, params, body } => {
+                let params: Vec<String> = params
+                    .iter()
+                    .map(|(name, dtype)| format!("{}: {}", name, dtype))
+                    .collect();
+                self.block(&format!("def {}({})", id, params.join(", ")), body)
+            }
+            Ast::ForLoop {
+                element,
+                elements,
+                block,
+            } => self.block(
+                &format!("for {} <- {}", expression(element), expression(elements)),
+                block,
+            ),
+            expr => self.line(&format!("{};", expression(expr))),
+        }
+    }
+}
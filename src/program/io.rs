@@ -0,0 +1,69 @@
+//! Streams a program reads its input from and prints its output to
+
+use std::cell::RefCell;
+use std::io::{BufRead, Read, Write};
+use std::rc::Rc;
+
+/// Input and output of a [`Program`](super::Program), shared by every nested block and
+/// function call. Defaults to the process stdin and stdout.
+///
+/// ```rust
+/// # use teolang::program::Program;
+/// # use std::{cell::RefCell, io::Cursor, rc::Rc};
+/// let output = Rc::new(RefCell::new(Vec::new()));
+/// let mut program = Program::builder()
+///     .source("name = input(); print(\"Hi {name}\");")
+///     .input(Rc::new(RefCell::new(Cursor::new("Teo"))))
+///     .output(output.clone())
+///     .build()
+///     .unwrap();
+/// program.run().unwrap();
+/// assert_eq!(output.borrow().as_slice(), b"Hi Teo\n");
+/// ```
+#[derive(Clone)]
+pub struct ProgramIo {
+    pub input: Rc<RefCell<dyn BufRead>>,
+    pub output: Rc<RefCell<dyn Write>>,
+}
+
+impl Default for ProgramIo {
+    fn default() -> Self {
+        ProgramIo {
+            input: Rc::new(RefCell::new(StdinReader::default())),
+            output: Rc::new(RefCell::new(std::io::stdout())),
+        }
+    }
+}
+
+/// Reads the process stdin one line at a time without holding its lock, so the host can
+/// still read stdin itself between two `input()` calls.
+#[derive(Default)]
+struct StdinReader {
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for StdinReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for StdinReader {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        if self.pos >= self.line.len() {
+            self.line.clear();
+            self.pos = 0;
+            std::io::stdin().lock().read_until(b'\n', &mut self.line)?;
+        }
+        Ok(&self.line[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.line.len());
+    }
+}
@@ -0,0 +1,223 @@
+use super::{CancelToken, Data, RuntimeError};
+use anyhow::Result;
+use std::cell::{Cell, RefCell};
+use std::rc::{Rc, Weak};
+use std::time::{Duration, Instant};
+
+/// Caps on what a program may do, so untrusted programs can be run safely.
+/// [`None`] means unlimited.
+///
+/// ```rust
+/// # use teolang::program::{Limits, Program, RuntimeError};
+/// let mut program = Program::builder()
+///     .source("x = 0; for i <- 0..1000000 { x += 1; }")
+///     .limits(Limits { max_steps: Some(1000), ..Limits::default() })
+///     .build()?;
+/// let error = program.run().err().unwrap();
+/// assert!(matches!(
+///     error.downcast_ref::<RuntimeError>(),
+///     Some(RuntimeError::StepLimitExceeded { limit: 1000 })
+/// ));
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Statements and loop iterations run, counted from [`Program::run`](super::Program::run)
+    /// or [`Program::call`](super::Program::call)
+    pub max_steps: Option<u64>,
+    /// How deep functions may call each other
+    pub max_call_depth: Option<usize>,
+    /// Size of the largest array or string, counted in elements (recursively) and bytes.
+    /// Checked whenever one is built or one of its elements is set:
+    ///
+    /// ```rust
+    /// # use teolang::program::{Engine, Limits, Program, RuntimeError};
+    /// let doubling = "a = [1, 1]; for i <- 0..22 { a[0] = a; a[1] = a; }";
+    /// // 3 elements and 3 strings of 10 bytes: 33
+    /// let filling = "a = [0, 0, 0]; for i <- 0..3 { a[i] = \"xxxxxxxxxx\"; }";
+    /// for engine in [Engine::Bytecode, Engine::TreeWalker] {
+    ///     let run = |source: &str, limit: usize| {
+    ///         Program::builder()
+    ///             .source(source)
+    ///             .limits(Limits { max_value_size: Some(limit), ..Limits::default() })
+    ///             .engine(engine)
+    ///             .build()?
+    ///             .run()
+    ///     };
+    ///     let error = run(doubling, 1000).err().unwrap();
+    ///     assert_eq!(
+    ///         error.downcast_ref(),
+    ///         Some(&RuntimeError::MemoryLimitExceeded { limit: 1000 })
+    ///     );
+    ///     assert!(run(filling, 33).is_ok());
+    ///     let error = run(filling, 32).err().unwrap();
+    ///     assert_eq!(
+    ///         error.downcast_ref(),
+    ///         Some(&RuntimeError::MemoryLimitExceeded { limit: 32 })
+    ///     );
+    /// }
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub max_value_size: Option<usize>,
+    /// Wall-clock time the program may run for, counted like [`Limits::max_steps`]
+    pub timeout: Option<Duration>,
+}
+
+impl Limits {
+    /// No limit at all, not even on call depth
+    pub fn unlimited() -> Limits {
+        Limits {
+            max_steps: None,
+            max_call_depth: None,
+            max_value_size: None,
+            timeout: None,
+        }
+    }
+
+    /// Fails if `data` is larger than [`Limits::max_value_size`]
+    pub fn check_size(&self, data: &Data) -> Result<()> {
+        match self.max_value_size {
+            Some(limit) if data.size() > limit => {
+                Err(RuntimeError::MemoryLimitExceeded { limit }.into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Collects `items` into an array without building more than [`Limits::max_value_size`]
+    /// elements
+    pub fn collect(&self, items: impl Iterator<Item = Data>) -> Result<Data> {
+        let array: Vec<Data> = match self.max_value_size {
+            Some(limit) => items.take(limit.saturating_add(1)).collect(),
+            None => items.collect(),
+        };
+        let array = Data::Array(Rc::new(array));
+        self.check_size(&array)?;
+        Ok(array)
+    }
+}
+
+impl Default for Limits {
+    /// Unlimited, except for a call depth that keeps runaway recursion from overflowing the
+    /// stack
+    fn default() -> Self {
+        Limits {
+            max_call_depth: Some(DEFAULT_MAX_CALL_DEPTH),
+            ..Limits::unlimited()
+        }
+    }
+}
+
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 200;
+
+/// Counters shared by a program and all of its nested blocks and calls
+#[derive(Debug, Default)]
+pub(crate) struct ExecState {
+    pub(crate) limits: Limits,
+    pub(crate) cancel: CancelToken,
+    steps: Cell<u64>,
+    depth: Cell<usize>,
+    deadline: Cell<Option<Instant>>,
+    /// Array an element was last stored into and its size, see [`ExecState::store_element`]
+    stored: RefCell<Option<(Weak<Vec<Data>>, usize)>>,
+}
+
+impl ExecState {
+    pub(crate) fn new(limits: Limits, cancel: CancelToken) -> ExecState {
+        ExecState {
+            limits,
+            cancel,
+            ..ExecState::default()
+        }
+    }
+
+    /// Starts counting steps and time from zero
+    pub(crate) fn start(&self) {
+        self.steps.set(0);
+        self.deadline
+            .set(self.limits.timeout.map(|timeout| Instant::now() + timeout));
+    }
+
+    /// Counts one step against [`Limits::max_steps`], then checks the deadline and the
+    /// cancel token
+    pub(crate) fn step(&self) -> Result<()> {
+        let steps = self.steps.get() + 1;
+        self.steps.set(steps);
+        if let Some(limit) = self.limits.max_steps {
+            if steps > limit {
+                return Err(RuntimeError::StepLimitExceeded { limit }.into());
+            }
+        }
+        if let (Some(deadline), Some(limit)) = (self.deadline.get(), self.limits.timeout) {
+            if Instant::now() >= deadline {
+                return Err(RuntimeError::Timeout { limit }.into());
+            }
+        }
+        if self.cancel.is_cancelled() {
+            return Err(RuntimeError::Cancelled.into());
+        }
+        Ok(())
+    }
+
+    /// Replaces the element `index` of `array` with what `store` makes of it, then checks
+    /// [`Limits::max_value_size`]. Returns `false` without storing if there is no such
+    /// element.
+    ///
+    /// Only the size change of the element is counted against the size of the array the last
+    /// time, so filling an array one element at a time does not measure all of it every time.
+    /// That size stays right: the [`Weak`] keeps another array from taking the place of this
+    /// one, and changing it in place anywhere else moves it away (see [`Rc::make_mut`]).
+    pub(crate) fn store_element(
+        &self,
+        array: &mut Rc<Vec<Data>>,
+        index: usize,
+        store: impl FnOnce(&mut Data) -> Result<()>,
+    ) -> Result<bool> {
+        // Let go of the weak reference first so the array is changed in place
+        let last = self
+            .stored
+            .take()
+            .filter(|(last, _)| last.as_ptr() == Rc::as_ptr(array))
+            .map(|(_, size)| size);
+        let Some(element) = Rc::make_mut(array).get_mut(index) else {
+            return Ok(false);
+        };
+        let Some(limit) = self.limits.max_value_size else {
+            store(element)?;
+            return Ok(true);
+        };
+        let replaced = element.size();
+        store(element)?;
+        let size = match last {
+            Some(size) => size - replaced + element.size(),
+            None => array.len() + array.iter().map(Data::size).sum::<usize>(),
+        };
+        if size > limit {
+            return Err(RuntimeError::MemoryLimitExceeded { limit }.into());
+        }
+        *self.stored.borrow_mut() = Some((Rc::downgrade(array), size));
+        Ok(true)
+    }
+
+    /// Enters a function call; the depth goes back down when the guard is dropped
+    pub(crate) fn enter_call(&self) -> Result<CallGuard<'_>> {
+        let depth = self.depth.get() + 1;
+        if let Some(limit) = self.limits.max_call_depth {
+            if depth > limit {
+                return Err(RuntimeError::CallDepthExceeded { limit }.into());
+            }
+        }
+        self.depth.set(depth);
+        Ok(CallGuard { state: self })
+    }
+}
+
+pub(crate) struct CallGuard<'a> {
+    state: &'a ExecState,
+}
+
+impl Drop for CallGuard<'_> {
+    fn drop(&mut self) {
+        self.state.depth.set(self.state.depth.get() - 1);
+    }
+}
@@ -1,587 +1,854 @@
 use crate::util::shell::Shell;
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use rust_decimal::prelude::*;
 use rust_decimal_macros::dec;
-use std::{collections::HashMap, iter::Product};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
 
 pub enum ReturnType {
     Ok(Data),
     None,
 }
 
+mod builder;
+pub mod bytecode;
+mod cancel;
+pub mod check;
+mod convert;
+pub mod debug;
+mod error;
+pub mod format;
+mod io;
+mod limits;
+pub mod native;
+pub mod optimize;
 pub mod parser;
+
+pub use builder::ProgramBuilder;
+pub use cancel::CancelToken;
+pub use error::RuntimeError;
+pub use io::ProgramIo;
+use limits::ExecState;
+pub use limits::Limits;
 pub struct Program {
     pub commands: parser::Ast,
     pub current_line: usize,
     pub variable: HashMap<String, Data>,
     pub function: HashMap<String, parser::Ast>,
-    pub std_commands: Vec<String>,
+    pub natives: native::NativeRegistry,
+    pub io: ProgramIo,
+    pub engine: Engine,
+    /// Arguments given to the program on the command line
+    pub args: Rc<[String]>,
+    pub(crate) state: Rc<ExecState>,
+    pub(crate) debugger: Option<Rc<debug::DebugState>>,
+}
+
+/// How a [`Program`] runs its code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Engine {
+    /// Walks the [`parser::Ast`] directly. Slower, kept to compare against.
+    TreeWalker,
+    /// Compiles to [`bytecode`] and runs it on a stack based virtual machine
+    #[default]
+    Bytecode,
+}
+
+impl FromStr for Engine {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "tree" => Ok(Engine::TreeWalker),
+            "bytecode" => Ok(Engine::Bytecode),
+            _ => Err(anyhow!(
+                "Engine does not exist: {}! Only engines exist are tree and bytecode",
+                s
+            )),
+        }
+    }
 }
 
-#[derive(Clone, PartialEq, Debug)]
+/// A value in a running program. Strings and arrays are reference counted, so reading a
+/// variable is cheap whatever it holds.
+///
+/// With serde, values map to plain JSON: `true`, `3`, `"text"` and `[1, 2]`.
+///
+/// ```rust
+/// # use teolang::program::Data;
+/// let data: Data = serde_json::from_str(r#"[1.5, "two", true]"#).unwrap();
+/// assert_eq!(data, Data::from(vec![Data::try_from(1.5).unwrap(), "two".into(), true.into()]));
+/// assert_eq!(serde_json::to_string(&data).unwrap(), r#"[1.5,"two",true]"#);
+/// ```
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
 pub enum Data {
-    String(String),
-    Number(Decimal),
-    Array(Vec<Data>),
     Bool(bool),
+    #[serde(with = "rust_decimal::serde::float")]
+    Number(Decimal),
+    /// Shared text; cloning only bumps a reference count
+    String(Rc<str>),
+    /// Shared elements, copied on write (see [`Data::array_mut`])
+    Array(Rc<Vec<Data>>),
 }
 
 impl Data {
-    fn as_number(&self) -> Decimal {
+    /// Size counted against [`Limits::max_value_size`]: bytes of a string, elements of an
+    /// array (nested arrays included), 1 for anything else
+    pub fn size(&self) -> usize {
         match self {
-            Data::Number(i) => *i,
-            Data::Bool(b) => {
-                if *b {
-                    dec!(1)
-                } else {
-                    dec!(0)
-                }
+            Data::String(s) => s.len(),
+            Data::Array(a) => a.len() + a.iter().map(Data::size).sum::<usize>(),
+            _ => 1,
+        }
+    }
+
+    pub fn data_type(&self) -> DataType {
+        match self {
+            Data::String(_) => DataType::String,
+            Data::Number(_) => DataType::Number,
+            Data::Array(_) => DataType::Array,
+            Data::Bool(_) => DataType::Bool,
+        }
+    }
+    /// Converts a Number or a Bool (`true` is 1) to a number, fails with
+    /// [`RuntimeError::WrongType`] for other types
+    ///
+    /// ```rust
+    /// # use teolang::program::{Data, RuntimeError};
+    /// assert_eq!(Data::from(true).as_number()?, 1.into());
+    /// let error = Data::from("3").as_number().unwrap_err();
+    /// assert!(matches!(error.downcast_ref(), Some(RuntimeError::WrongType { .. })));
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn as_number(&self) -> Result<Decimal> {
+        match self {
+            Data::Number(i) => Ok(*i),
+            Data::Bool(b) => Ok(if *b { dec!(1) } else { dec!(0) }),
+            other => Err(RuntimeError::WrongType {
+                message: format!("Expected a Number, got {}", other.data_type()),
             }
-            _ => panic!("Data is not convertable"),
+            .into()),
         }
     }
-    fn as_string(&self) -> String {
+    /// Converts data to the text `print` shows. Arrays are written the way they are in Teo
+    /// code, like `[1, "two"]`.
+    pub fn as_string(&self) -> String {
         match self {
             Data::Number(i) => i.normalize().to_string(),
-            Data::String(i) => i.clone(),
+            Data::String(i) => i.to_string(),
             Data::Bool(b) => b.to_string(),
-            _ => panic!("Data is not convertable"),
+            Data::Array(a) => {
+                let elements: Vec<String> = a.iter().map(Data::as_code).collect();
+                format!("[{}]", elements.join(", "))
+            }
+        }
+    }
+    /// Converts data to the text Teo code writes it with: the text of
+    /// [`as_string`](Data::as_string), with strings in quotes.
+    ///
+    /// ```rust
+    /// # use teolang::program::Data;
+    /// assert_eq!(Data::from("hi").as_code(), "\"hi\"");
+    /// let array = Data::from(vec![Data::from(1), Data::from("two")]);
+    /// assert_eq!(array.as_code(), "[1, \"two\"]");
+    /// ```
+    pub fn as_code(&self) -> String {
+        match self {
+            Data::String(s) => format!("{:?}", s),
+            other => other.as_string(),
+        }
+    }
+    /// Elements of an array, to change in place. They are copied first if another value
+    /// shares them. [`None`] for other types.
+    pub fn array_mut(&mut self) -> Option<&mut Vec<Data>> {
+        match self {
+            Data::Array(a) => Some(Rc::make_mut(a)),
+            _ => None,
         }
     }
 }
 
-macro_rules! matchcmd {
-    ($id:expr, $errmessage:expr, {$($function:expr => $body:block),+}) => {
-        match $id.as_str() {
-            $(
-                #[cfg(feature = $function)]
-                $function => $body,
-                #[cfg(not(feature = $function))]
-                $function => Err(anyhow!("Function {} is not enabled!", $function)),
-            )+
-            _ => Err(anyhow!($errmessage)),
+/// Type of a [`Data`], as written in function parameters (`n: Number`)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DataType {
+    String,
+    Number,
+    Array,
+    Bool,
+    /// Any type is accepted
+    Any,
+}
+
+impl DataType {
+    pub fn matches(&self, data: &Data) -> bool {
+        *self == DataType::Any || *self == data.data_type()
+    }
+}
+
+impl FromStr for DataType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "String" => Ok(DataType::String),
+            "Number" => Ok(DataType::Number),
+            "Array" => Ok(DataType::Array),
+            "Bool" => Ok(DataType::Bool),
+            _ => Err(anyhow!(
+                "Type does not exist: {}! Only types exist are Number, String, Bool and Array",
+                s
+            )),
         }
-    };
+    }
+}
+
+impl fmt::Display for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// Numbers from `start` towards `end`, `step` apart. Produced lazily so `for` can count
+/// without building an array first.
+pub struct NumberRange {
+    next: Decimal,
+    end: Decimal,
+    step: Decimal,
+    inclusive: bool,
 }
 
-macro_rules! fep {
-    ($program:ident, $args:expr, $parseto:ident, $writer:ident $body:block) => {
-        for arg in $args {
-            let $parseto = arg.evaluate(&$program, $writer).unwrap();
-            $body
+impl NumberRange {
+    pub fn new(start: Decimal, end: Decimal, step: Decimal, inclusive: bool) -> Result<Self> {
+        if step.is_zero() {
+            return Err(anyhow!("Range step cannot be 0!"));
         }
-    };
+        Ok(NumberRange {
+            next: start,
+            end,
+            step,
+            inclusive,
+        })
+    }
 }
 
-macro_rules! unrecov_err {
-    ($shell:ident, $($errormessage:tt)*) => {
-        let mesg = format!($($errormessage)*);
-        let _ = $shell.error(format!("Unrecoverable error: {}", mesg));
-        panic!("Unrecoverable error!")
+impl Iterator for NumberRange {
+    type Item = Decimal;
+
+    fn next(&mut self) -> Option<Decimal> {
+        let current = self.next;
+        let in_range = match (self.step.is_sign_positive(), self.inclusive) {
+            (true, true) => current <= self.end,
+            (true, false) => current < self.end,
+            (false, true) => current >= self.end,
+            (false, false) => current > self.end,
+        };
+        if !in_range {
+            return None;
+        }
+        self.next += self.step;
+        Some(current)
     }
 }
 
 impl Program {
-    pub fn run_loop(
-        &mut self,
-        mut writer: &mut impl std::io::Write,
-        shell: &mut Shell,
-    ) -> Result<ReturnType> {
-        match &self.commands {
-            parser::Ast::Block(commands) => {
-                for command in commands {
-                    match command {
-                        parser::Ast::Set { id, expr } => {
-                            let value = expr.evaluate(&self, writer);
-                            match id.as_ref() {
-                                parser::Ast::ArrayAccess {
-                                    expr: array_id,
-                                    whereto: k,
-                                } => {
-                                    if let parser::Ast::Identifier(id) =
-                                        &*std::rc::Rc::clone(&array_id)
-                                    {
-                                        if let Data::Array(arr) = &self.variable[id] {
-                                            let mut a = arr.clone();
-                                            a[k.evaluate(&self, writer)
-                                                .unwrap()
-                                                .as_number()
-                                                .to_usize()
-                                                .unwrap()] = value.unwrap();
-                                            self.variable.insert(id.to_string(), Data::Array(a));
-                                        }
-                                    } else {
-                                        panic!("{:?} {:?}", array_id, k)
-                                    }
-                                }
+    /// Starts building a program, see [`ProgramBuilder`]
+    pub fn builder() -> ProgramBuilder {
+        ProgramBuilder::default()
+    }
 
-                                _ => {
-                                    self.variable.insert(id.to_string(), value.unwrap());
-                                }
-                            };
-                        }
-                        parser::Ast::If { condition, block } => {
-                            let conditionresult = condition.evaluate(&self, writer);
-                            match conditionresult.unwrap() {
-                                Data::Bool(e) => {
-                                    if e {
-                                        let mut program = Program {
-                                            commands: *block.clone(),
-                                            current_line: 0,
-                                            variable: self.variable.clone(),
-                                            function: self.function.clone(),
-                                            std_commands: self.std_commands.clone(),
-                                        };
-                                        if let Ok(returnval) = program.run_loop(writer, shell) {
-                                            self.variable = program.variable;
-                                            match returnval {
-                                                ReturnType::Ok(x) => return Ok(ReturnType::Ok(x)),
-                                                ReturnType::None => {}
-                                            }
-                                        } else {
-                                            panic!("Code block within If-else panicked!");
-                                        }
-                                    }
-                                }
-                                _ => unimplemented!(),
-                            };
-                        }
-                        parser::Ast::FunctionDefinition { id, params, body } => {
-                            if self.function.contains_key(id) | self.std_commands.contains(id) {
-                                panic!("Function `{}` already exist!", id);
-                            }
-                            self.function.insert(
-                                id.clone(),
-                                parser::Ast::FunctionDefinition {
-                                    id: id.clone(),
-                                    params: params.clone(),
-                                    body: body.clone(),
-                                },
-                            );
-                        }
-                        parser::Ast::FunctionCall { id, args } => {
-                            let std_functions = self.std_commands.clone();
-                            if std_functions.contains(id) {
-                                matchcmd!(id, "Function isn't enabled.", {
-                                    "print" => {
-                                        fep!(self, args, value, writer {
-                                            println!("{}", value.as_string());
-                                            write!(&mut writer, "{}", value.as_string()).unwrap();
-                                        });
-                                            Ok(Data::Number(dec!(0)))
-                                    },
-                                    "return" => {
-                                        if let Some(arg) = args.first() {
-                                            let value = arg.evaluate(&self, writer).unwrap();
-                                            return Ok(ReturnType::Ok(value))
-                                        } else {
-                                            Err(anyhow!("Need to return only one value!"))
-                                        }
-                                    },
-                                    "input" => {
-                                        let mut userInput = String::new();
-                                        let stdin = std::io::stdin();
-                                            stdin.read_line(&mut userInput);
-                                        Ok(Data::Number(dec!(0)))
+    /// Runs the program from the top, registering its functions along the way
+    pub fn run(&mut self) -> Result<ReturnType> {
+        self.state.start();
+        match self.engine {
+            Engine::TreeWalker => self.run_loop(&mut Shell::new()),
+            Engine::Bytecode => bytecode::run(self),
+        }
+    }
+
+    /// Sets a global variable, replacing any previous value
+    pub fn set_global(&mut self, name: &str, value: impl Into<Data>) {
+        self.variable.insert(name.to_string(), value.into());
+    }
+
+    /// Gets a global variable, for example one set by the program after [`Program::run`]
+    pub fn get_global(&self, name: &str) -> Option<&Data> {
+        self.variable.get(name)
+    }
+
+    /// Calls a standard function or a function defined by the program (`def`), which must
+    /// have been run first so the definition is known.
+    ///
+    /// ```rust
+    /// # use teolang::program::{Data, Program};
+    /// let mut program = Program::builder()
+    ///     .source("def double(n: Number) { return(n * 2); }")
+    ///     .build()
+    ///     .unwrap();
+    /// program.run().unwrap();
+    /// let result = program.call("double", vec![21.into()]).unwrap();
+    /// assert_eq!(i64::try_from(result).unwrap(), 42);
+    /// ```
+    pub fn call(&self, name: &str, args: Vec<Data>) -> Result<Data> {
+        self.state.start();
+        match self.engine {
+            Engine::TreeWalker => self.invoke(name, args),
+            Engine::Bytecode => bytecode::call(self, name, args),
+        }
+    }
+
+    /// Limits the program runs under (set with [`ProgramBuilder::limits`])
+    pub fn limits(&self) -> Limits {
+        self.state.limits
+    }
+
+    /// Token that stops the program when cancelled (set with [`ProgramBuilder::cancel_token`])
+    pub fn cancel_token(&self) -> CancelToken {
+        self.state.cancel.clone()
+    }
+
+    /// Creates a program for a nested code block, sharing this program's functions
+    /// Program sharing everything with this one but its variables, to run a block with
+    /// [`Program::run_block`]
+    fn child(&self, variable: HashMap<String, Data>) -> Program {
+        Program {
+            commands: parser::Ast::Block(Vec::new()),
+            current_line: 0,
+            variable,
+            function: self.function.clone(),
+            natives: self.natives.clone(),
+            io: self.io.clone(),
+            engine: self.engine,
+            args: self.args.clone(),
+            state: self.state.clone(),
+            debugger: self.debugger.clone(),
+        }
+    }
+
+    /// Runs [`Program::commands`] with the tree walker
+    pub fn run_loop(&mut self, shell: &mut Shell) -> Result<ReturnType> {
+        // Moved out while they run rather than copied, and put back even if they fail
+        let commands = std::mem::replace(&mut self.commands, parser::Ast::Block(Vec::new()));
+        let returned = statements(&commands).and_then(|block| self.run_block(block, shell));
+        self.commands = commands;
+        returned
+    }
+
+    #[allow(clippy::only_used_in_recursion)]
+    fn run_block(&mut self, block: &[parser::Statement], shell: &mut Shell) -> Result<ReturnType> {
+        for statement in block {
+            let command = &statement.ast;
+            if let parser::Ast::Comment(_) = command {
+                continue;
+            }
+            self.current_line = statement.line;
+            self.hook(command, |debugger, at| debugger.pause(at))?;
+            self.state.step()?;
+            match command {
+                parser::Ast::Set { id, op, expr } => {
+                    let mut value = expr.evaluate(self)?;
+                    self.hook(command, |debugger, at| debugger.evaluated(at, expr, &value))?;
+                    match id.as_ref() {
+                        parser::Ast::ArrayAccess {
+                            expr: array_id,
+                            whereto: k,
+                        } => {
+                            if let parser::Ast::Identifier(id) = array_id.as_ref() {
+                                let index = k.evaluate(self)?;
+                                let array = self.variable.get_mut(id).ok_or_else(|| {
+                                    RuntimeError::UndefinedVariable {
+                                        name: id.to_string(),
                                     }
-                                }
-                                );
-                            } else if let Some(func) = self.function.get(id) {
-                                match func {
-                                    parser::Ast::FunctionDefinition { params, body, .. } => {
-                                        if params.len() < args.len() {
-                                            panic!("Too many argument!");
-                                        }
-                                        if params.len() > args.len() {
-                                            panic!("Not enough argument!");
-                                        }
-                                        let mut local_variables = HashMap::new();
-                                        for (i, arg) in args.iter().enumerate() {
-                                            let (name, dtype) = &params[i];
-                                            let value = arg.evaluate(&self, writer).unwrap();
-                                            match dtype.as_str() {
-                                        "Number" => {
-                                            if let Data::Number(_) = value {
-                                            } else {
-                                                panic!("Wrong type for function: expected {}!", dtype);
-                                            }
-                                        }
-                                        "String" => {
-                                            if let Data::String(_) = value {
-                                            } else {
-                                                panic!("Wrong type for function: expected {}!", dtype);
-                                            }
+                                })?;
+                                if let Data::Array(a) = array {
+                                    let position = match &index {
+                                        Data::Number(n) => n.to_usize(),
+                                        _ => None,
+                                    };
+                                    let stored = match position {
+                                        Some(i) => self.state.store_element(a, i, |element| {
+                                            *element = match op {
+                                                Some(op) => binary_op(op, element, &value)?,
+                                                None => value,
+                                            };
+                                            Ok(())
+                                        })?,
+                                        None => false,
+                                    };
+                                    if !stored {
+                                        return Err(RuntimeError::IndexOutOfBounds {
+                                            message: format!(
+                                                "Error: index out of bounds: {}[{:?}]",
+                                                id, index
+                                            ),
                                         }
-                                        "Array" => {
-                                            if let Data::Array(_) = value {
-                                            } else {
-                                                panic!("Wrong type for function: expected {}!", dtype);
-                                            }
-                                        }
-                                        "Bool" => {
-                                            if let Data::Bool(_) = value {
-                                            } else {
-                                                panic!("Wrong type for function: expected {}!", dtype);
-                                            }
-                                        }
-                                        _ => panic!("Type does not exist: {}! Only types exist are Integer, String, Bool and Array", dtype),
+                                        .into());
                                     }
-                                            local_variables.insert(name.clone(), value);
-                                        }
-                                        let mut program = Program {
-                                            commands: *body.clone(),
-                                            current_line: 0,
-                                            variable: local_variables,
-                                            function: self.function.clone(),
-                                            std_commands: self.std_commands.clone(),
-                                        };
-                                        if let Ok(_returncode) = program.run_loop(writer, shell) {
-                                        } else {
-                                            panic!("Function `{}` panicked!", id);
-                                        }
+                                    let array = &self.variable[id];
+                                    self.hook(command, |debugger, at| {
+                                        debugger.assigned(at, id, array)
+                                    })?;
+                                } else {
+                                    return Err(RuntimeError::WrongType {
+                                        message: format!(
+                                            "Error: cannot index into {}",
+                                            array.data_type()
+                                        ),
                                     }
-                                    _ => panic!("`{}` is not a function!", id),
+                                    .into());
                                 }
                             } else {
-                                panic!("Function `{}` is not defined!", id);
+                                return Err(anyhow!("Error: only variables can be indexed into"));
                             }
                         }
-                        parser::Ast::ForLoop {
-                            element,
-                            elements,
-                            block,
-                        } => {
-                            let collection = elements.evaluate(&self, writer)?;
-                            match collection {
-                                Data::Array(array) => {
-                                    for item in array.iter() {
-                                        let mut local_variables = self.variable.clone();
-                                        local_variables.insert(element.to_string(), item.clone());
-                                        let mut program = Program {
-                                            commands: *block.clone(),
-                                            current_line: 0,
-                                            variable: local_variables,
-                                            function: self.function.clone(),
-                                            std_commands: self.std_commands.clone(),
-                                        };
-                                        if program.run_loop(writer, shell).is_err() {
-                                            panic!("For loop panicked!");
+
+                        _ => {
+                            if let Some(op) = op {
+                                let current =
+                                    self.variable.get(&id.to_string()).ok_or_else(|| {
+                                        RuntimeError::UndefinedVariable {
+                                            name: id.to_string(),
                                         }
-                                        self.variable = program.variable;
-                                    }
-                                }
-                                _ => {
-                                    panic!("For loop collection must be an array!");
-                                }
+                                    })?;
+                                value = binary_op(op, current, &value)?;
                             }
+                            let name = id.to_string();
+                            self.hook(command, |debugger, at| {
+                                debugger.assigned(at, &name, &value)
+                            })?;
+                            self.variable.insert(name, value);
                         }
-                        _ => {
-                            unimplemented!()
+                    };
+                }
+                parser::Ast::If { condition, block } => {
+                    let value = condition.evaluate(self)?;
+                    self.hook(command, |debugger, at| {
+                        debugger.evaluated(at, condition, &value)
+                    })?;
+                    match value {
+                        Data::Bool(true) => {
+                            let mut program = self.child(self.variable.clone());
+                            let returnval = program.run_block(statements(block)?, shell)?;
+                            self.variable = program.variable;
+                            if let ReturnType::Ok(x) = returnval {
+                                return Ok(ReturnType::Ok(x));
+                            }
                         }
+                        Data::Bool(false) => {}
+                        other => {
+                            return Err(RuntimeError::WrongType {
+                                message: format!("If condition must be a Bool, got {:?}", other),
+                            }
+                            .into())
+                        }
+                    };
+                }
+                parser::Ast::FunctionDefinition { id, .. } => {
+                    if self.function.contains_key(id) | self.natives.contains(id) | (id == "return")
+                    {
+                        return Err(anyhow!("Function `{}` already exist!", id));
                     }
+                    self.function.insert(id.clone(), command.clone());
+                }
+                parser::Ast::FunctionCall { id, args } if id == "return" => {
+                    if !cfg!(feature = "return") {
+                        return Err(anyhow!("Function return is not enabled!"));
+                    }
+                    if let [arg] = args.as_slice() {
+                        let value = arg.evaluate(self)?;
+                        self.hook(command, |debugger, at| debugger.evaluated(at, arg, &value))?;
+                        return Ok(ReturnType::Ok(value));
+                    } else {
+                        return Err(anyhow!("Need to return only one value!"));
+                    }
+                }
+                parser::Ast::ForLoop {
+                    element,
+                    elements,
+                    block,
+                } => {
+                    let collection: Box<dyn Iterator<Item = Data>> = match elements.as_ref() {
+                        parser::Ast::Range {
+                            start,
+                            end,
+                            step,
+                            inclusive,
+                        } => Box::new(
+                            evaluate_range(start, end, step, *inclusive, self)?.map(Data::Number),
+                        ),
+                        _ => match elements.evaluate(self)? {
+                            Data::Array(array) => {
+                                let value = Data::Array(array.clone());
+                                self.hook(command, |debugger, at| {
+                                    debugger.evaluated(at, elements, &value)
+                                })?;
+                                Box::new((0..array.len()).map(move |i| array[i].clone()))
+                            }
+                            other => {
+                                return Err(RuntimeError::WrongType {
+                                    message: format!(
+                                        "For loop collection must be an array, got {:?}",
+                                        other
+                                    ),
+                                }
+                                .into())
+                            }
+                        },
+                    };
+                    for item in collection {
+                        self.state.step()?;
+                        let name = element.to_string();
+                        self.hook(command, |debugger, at| debugger.assigned(at, &name, &item))?;
+                        // Moved rather than copied, so arrays the block changes are not
+                        // shared and copied again at each iteration
+                        let mut local_variables = std::mem::take(&mut self.variable);
+                        local_variables.insert(element.to_string(), item);
+                        let mut program = self.child(local_variables);
+                        let returnval = program.run_block(statements(block)?, shell);
+                        self.variable = program.variable;
+                        let returnval = returnval?;
+                        if let ReturnType::Ok(x) = returnval {
+                            return Ok(ReturnType::Ok(x));
+                        }
+                    }
+                }
+                // Any other statement is an expression (function calls included) whose value
+                // is thrown away
+                expr => {
+                    expr.evaluate(self)?;
                 }
             }
-            _ => unimplemented!("{:?}", &self.commands),
         }
         Ok(ReturnType::None)
     }
+
+    /// Calls `hook` with the debugger, if any, and where `statement` stands
+    fn hook(
+        &self,
+        statement: &parser::Ast,
+        hook: impl FnOnce(&mut dyn debug::Debugger, &debug::Pause<'_>) -> Result<()>,
+    ) -> Result<()> {
+        match &self.debugger {
+            Some(debugger) => debugger.hook(self.current_line, statement, &self.variable, hook),
+            None => Ok(()),
+        }
+    }
+
+    /// Evaluates the arguments of a function call then calls the function. This is the only
+    /// place calls are evaluated from, whether the call is a statement or part of an
+    /// expression.
+    fn evaluate_call(&self, id: &str, args: &[parser::Ast]) -> Result<Data> {
+        if id == "return" {
+            return Err(anyhow!("`return` can only be used as a statement!"));
+        }
+        let mut values = Vec::with_capacity(args.len());
+        for arg in args {
+            values.push(arg.evaluate(self)?);
+        }
+        self.invoke(id, values)
+    }
+
+    /// Checks the arguments of a native function then calls it with the program's input and
+    /// output
+    fn invoke_native(&self, native: &dyn native::NativeFunction, args: Vec<Data>) -> Result<Data> {
+        native::check_args(native, &args)?;
+        let mut ctx = native::NativeContext {
+            input: &mut *self.io.input.borrow_mut(),
+            output: &mut *self.io.output.borrow_mut(),
+            limits: &self.state.limits,
+            args: &self.args,
+        };
+        let result = native.call(&mut ctx, args)?;
+        self.state.limits.check_size(&result)?;
+        Ok(result)
+    }
+
+    /// Calls a standard or user-defined function with evaluated arguments
+    fn invoke(&self, id: &str, args: Vec<Data>) -> Result<Data> {
+        if let Some(native) = self.natives.get(id).cloned() {
+            self.invoke_native(native.as_ref(), args)
+        } else if let Some(func) = self.function.get(id) {
+            match func {
+                parser::Ast::FunctionDefinition { params, body, .. } => {
+                    if params.len() < args.len() {
+                        return Err(RuntimeError::WrongArguments {
+                            message: format!("Too many argument for `{}`!", id),
+                        }
+                        .into());
+                    }
+                    if params.len() > args.len() {
+                        return Err(RuntimeError::WrongArguments {
+                            message: format!("Not enough argument for `{}`!", id),
+                        }
+                        .into());
+                    }
+                    let mut local_variables = HashMap::new();
+                    for ((name, dtype), value) in params.iter().zip(args) {
+                        if !dtype.parse::<DataType>()?.matches(&value) {
+                            return Err(RuntimeError::WrongArguments {
+                                message: format!(
+                                    "Wrong type for function `{}`: expected {} for `{}`, got {:?}!",
+                                    id, dtype, name, value
+                                ),
+                            }
+                            .into());
+                        }
+                        local_variables.insert(name.clone(), value);
+                    }
+                    let _depth = self.state.enter_call()?;
+                    let _frame = self
+                        .debugger
+                        .as_ref()
+                        .map(|debugger| debugger.enter(id, self.current_line));
+                    let mut program = self.child(local_variables);
+                    match program
+                        .run_block(statements(body)?, &mut Shell::new())
+                        .with_context(|| format!("Function `{}` failed", id))?
+                    {
+                        ReturnType::None => Ok(Data::Number(dec!(0))),
+                        ReturnType::Ok(red) => Ok(red),
+                    }
+                }
+                _ => Err(RuntimeError::WrongType {
+                    message: format!("`{}` is not a function", id),
+                }
+                .into()),
+            }
+        } else {
+            Err(RuntimeError::UndefinedFunction {
+                name: id.to_string(),
+            }
+            .into())
+        }
+    }
+}
+
+/// Statements of a block ([`parser::Ast::Block`])
+fn statements(block: &parser::Ast) -> Result<&[parser::Statement]> {
+    match block {
+        parser::Ast::Block(statements) => Ok(statements),
+        other => Err(anyhow!(
+            "Error: expected a block of statements, got {:?}",
+            other
+        )),
+    }
+}
+
+/// Applies a binary operator; also used by compound assignment (`x += 1`)
+fn binary_op(op: &str, left_value: &Data, right_value: &Data) -> Result<Data> {
+    let number = |data: &Data| match data {
+        Data::Number(n) => Ok(*n),
+        Data::Bool(b) => Ok(if *b { dec!(1) } else { dec!(0) }),
+        other => Err(RuntimeError::WrongType {
+            message: format!("Operator {} needs numbers, got {:?}", op, other.data_type()),
+        }),
+    };
+    let f1 = number(left_value)?;
+    // The right operand of a factorial is a placeholder
+    let f2 = if op == "!" {
+        dec!(0)
+    } else {
+        number(right_value)?
+    };
+    let overflow = || anyhow!("Error: {} {} {} overflows", f1, op, f2);
+    match op {
+        "+" => f1.checked_add(f2).map(Data::Number).ok_or_else(overflow),
+        "-" => f1.checked_sub(f2).map(Data::Number).ok_or_else(overflow),
+        "*" => f1.checked_mul(f2).map(Data::Number).ok_or_else(overflow),
+        "/" if f2.is_zero() => Err(RuntimeError::DivisionByZero.into()),
+        "/" => f1.checked_div(f2).map(Data::Number).ok_or_else(overflow),
+        "==" => Ok(Data::Bool(f1 == f2)),
+        "!=" => Ok(Data::Bool(f1 != f2)),
+        "!" => {
+            if !f1.is_integer() || f1.is_sign_negative() {
+                return Err(anyhow!("Error: factorial needs a whole number, got {}", f1));
+            }
+            let mut product = dec!(1);
+            let mut n = dec!(2);
+            while n <= f1 {
+                product = product
+                    .checked_mul(n)
+                    .ok_or_else(|| anyhow!("Error: {}! overflows", f1))?;
+                n += dec!(1);
+            }
+            Ok(Data::Number(product))
+        }
+        "<" => Ok(Data::Bool(f1 < f2)),
+        ">" => Ok(Data::Bool(f1 > f2)),
+        "<=" => Ok(Data::Bool(f1 <= f2)),
+        ">=" => Ok(Data::Bool(f1 >= f2)),
+        "^" => f1.checked_powd(f2).map(Data::Number).ok_or_else(overflow),
+        _ => Err(anyhow!("{} is not a valid binary operator", op)),
+    }
+}
+
+fn evaluate_range(
+    start: &parser::Ast,
+    end: &parser::Ast,
+    step: &Option<Box<parser::Ast>>,
+    inclusive: bool,
+    program: &Program,
+) -> Result<NumberRange> {
+    let step = match step {
+        Some(step) => Some(step.evaluate(program)?),
+        None => None,
+    };
+    number_range(
+        start.evaluate(program)?,
+        end.evaluate(program)?,
+        step,
+        inclusive,
+    )
+}
+
+/// Creates the range `start..end` (`start..=end` if inclusive), `step` (1 if not given) apart
+fn number_range(
+    start: Data,
+    end: Data,
+    step: Option<Data>,
+    inclusive: bool,
+) -> Result<NumberRange> {
+    let bound = |data: Data| match data {
+        Data::Number(n) => Ok(n),
+        other => Err(RuntimeError::WrongType {
+            message: format!("Range bounds must be numbers, got {:?}", other),
+        }),
+    };
+    let step = match step {
+        Some(step) => bound(step)?,
+        None => dec!(1),
+    };
+    NumberRange::new(bound(start)?, bound(end)?, step, inclusive)
+}
+
+/// [`RuntimeError::WrongType`] with `message`
+fn wrong_type(message: &str) -> anyhow::Error {
+    RuntimeError::WrongType {
+        message: message.to_string(),
+    }
+    .into()
+}
+
+/// Error for an index or slice past the end of `array`
+fn out_of_bounds(array: &[Data]) -> RuntimeError {
+    RuntimeError::IndexOutOfBounds {
+        message: format!("Error: index out of bounds: {:?}", array),
+    }
+}
+
+/// Gets `array[index]`
+fn index_array(array: Data, index: Data) -> Result<Data> {
+    let array = match array {
+        Data::Array(a) => a,
+        _ => return Err(wrong_type("Error: expected an array")),
+    };
+    let index = match index {
+        Data::Number(n) if n.is_integer() && !n.is_sign_negative() => n,
+        _ => return Err(wrong_type("Error: expected an index or a slice")),
+    };
+    array
+        .get(index.to_usize().unwrap())
+        .cloned()
+        .ok_or_else(|| out_of_bounds(&array).into())
+}
+
+/// Gets `array[from..to]`, both ends included; `from` is 0 and `to` the last index if not
+/// given
+fn slice_array(array: Data, from: Option<Data>, to: Option<Data>) -> Result<Data> {
+    let array = match array {
+        Data::Array(a) => a,
+        _ => return Err(wrong_type("Error: expected an array")),
+    };
+    let bound = |data: Data| match data {
+        Data::Number(n) => n
+            .to_usize()
+            .ok_or_else(|| wrong_type("Error: expected an index or a slice")),
+        _ => Err(wrong_type("Error: expected an index or a slice")),
+    };
+    let start_index = from.map(bound).transpose()?.unwrap_or(0);
+    let end_index = match to {
+        Some(to) => bound(to)?,
+        None => array.len().saturating_sub(1),
+    };
+    match array.get(start_index..=end_index) {
+        Some(slice) => Ok(Data::from(slice.to_vec())),
+        None => Err(out_of_bounds(&array).into()),
+    }
 }
 
 trait Evaluate {
-    fn evaluate(&self, program: &Program, writer: &mut impl std::io::Write) -> Result<Data>;
+    fn evaluate(&self, program: &Program) -> Result<Data>;
 }
 
 impl Evaluate for parser::Ast {
-    fn evaluate(&self, program: &Program, writer: &mut impl std::io::Write) -> Result<Data> {
+    fn evaluate(&self, program: &Program) -> Result<Data> {
         let variables = &program.variable;
         match self {
             parser::Ast::Int(i) => Ok(Data::Number(*i)),
             parser::Ast::Bool(b) => Ok(Data::Bool(*b)),
             parser::Ast::Identifier(id) => match variables.get(id) {
                 Some(value) => Ok(value.clone()),
-                None => Err(anyhow!("Error: variable not found: {}", id)),
+                None => Err(RuntimeError::UndefinedVariable { name: id.clone() }.into()),
             },
             parser::Ast::BinaryOp { op, left, right } => {
-                let left_value = left.evaluate(program, writer).unwrap();
-                let right_value = right.evaluate(program, writer).unwrap();
-                let f1 = left_value.as_number();
-                let f2 = right_value.as_number();
-                match op.as_str() {
-                    "+" => Ok(Data::Number(f1 + f2)),
-                    "-" => Ok(Data::Number(f1 - f2)),
-                    "*" => Ok(Data::Number(f1 * f2)),
-                    "/" => Ok(Data::Number(f1 / f2)),
-                    "==" => Ok(Data::Bool(f1 == f2)),
-                    "!=" => Ok(Data::Bool(f1 != f2)),
-                    "!" => Ok(Data::Number(
-                        Decimal::from_i32(
-                            (1..=if f1.is_integer() {
-                                f1.to_i32().unwrap()
-                            } else {
-                                panic!("cannot use decimal value to do factorial")
-                            })
-                                .product(),
-                        )
-                        .unwrap(),
-                    )),
-                    "<" => Ok(Data::Bool(f1 < f2)),
-                    ">" => Ok(Data::Bool(f1 > f2)),
-                    "<=" => Ok(Data::Bool(f1 <= f2)),
-                    ">=" => Ok(Data::Bool(f1 >= f2)),
-                    "^" => Ok(Data::Number(f1.powd(f2))),
-                    _ => panic!("{} is not a valid binary operator", op),
+                let left_value = left.evaluate(program)?;
+                let right_value = right.evaluate(program)?;
+                binary_op(op, &left_value, &right_value)
+            }
+            parser::Ast::String(i) => Ok(Data::String(i.as_str().into())),
+            parser::Ast::Interpolated(parts) => {
+                let mut string = String::new();
+                for part in parts {
+                    string += &part.evaluate(program)?.as_string();
                 }
+                let string = Data::String(string.into());
+                program.state.limits.check_size(&string)?;
+                Ok(string)
             }
-            parser::Ast::String(i) => Ok(Data::String(i.clone())),
+            parser::Ast::Range {
+                start,
+                end,
+                step,
+                inclusive,
+            } => program
+                .state
+                .limits
+                .collect(evaluate_range(start, end, step, *inclusive, program)?.map(Data::Number)),
             parser::Ast::Array(elements) => {
                 let mut array_data = Vec::new();
                 for element in elements {
-                    let element_data = element.evaluate(program, writer).unwrap();
+                    let element_data = element.evaluate(program)?;
                     array_data.push(element_data);
                 }
-                Ok(Data::Array(array_data))
+                let array = Data::from(array_data);
+                program.state.limits.check_size(&array)?;
+                Ok(array)
             }
             parser::Ast::ArrayAccess { expr, whereto } => {
-                // Evaluate the array expression to get the array
-                let array = match expr.evaluate(program, writer)? {
-                    Data::Array(a) => a,
-                    _ => return Err(anyhow!("Error: expected an array")),
-                };
-
-                // Check if the whereto is a single index or a slice
+                let array = expr.evaluate(program)?;
                 match &**whereto {
-                    // Single index
-                    parser::Ast::Int(index) => {
-                        // Access the array element at the given index
-                        array
-                            .get(index.to_usize().unwrap())
-                            .cloned()
-                            .ok_or_else(|| anyhow!("Error: index out of bounds: {:?}", array))
-                    }
-                    // Slice
                     parser::Ast::AstSlice { from, to } => {
-                        // Evaluate the slice indices expressions to get the start and end indices
-                        let start_index =
-                            match from.as_ref().map(|expr| expr.evaluate(program, writer)) {
-                                Some(Ok(Data::Number(n))) => n.to_usize().unwrap(),
-                                Some(Err(e)) => return Err(e),
-                                None => 0,
-                                _ => unimplemented!(),
-                            };
-                        let end_index = match to.as_ref().map(|expr| expr.evaluate(program, writer))
-                        {
-                            Some(Ok(Data::Number(n))) => n.to_usize().unwrap(),
-                            Some(Err(e)) => return Err(e),
-                            None => array.len() - 1,
-                            _ => unimplemented!(),
-                        };
-
-                        // Return a slice of the array from start_index to end_index
-                        Ok(Data::Array(array[start_index..=end_index].to_vec()))
+                        let from = from
+                            .as_ref()
+                            .map(|from| from.evaluate(program))
+                            .transpose()?;
+                        let to = to.as_ref().map(|to| to.evaluate(program)).transpose()?;
+                        slice_array(array, from, to)
                     }
-                    _ => Err(anyhow!("Error: expected an index or a slice")),
+                    index => index_array(array, index.evaluate(program)?),
                 }
             }
 
             parser::Ast::ArrayCall { id, k } => {
-                if let Some(array) = variables.get(id) {
-                    if let Data::Array(elements) = array {
-                        let index = k
-                            .evaluate(program, writer)
-                            .unwrap()
-                            .as_number()
-                            .to_usize()
-                            .unwrap();
-                        if index >= elements.len() {
-                            panic!("Error: array index out of bounds");
-                        }
-                        Ok(elements[index].clone())
-                    } else {
-                        Err(anyhow!(format!(
-                            "Variable {} is not an array, cannot modify!",
-                            id
-                        )))
-                    }
-                } else {
-                    panic!("Error: array variable not found: {}", id);
-                }
-            }
-            parser::Ast::FunctionCall { id, args } => {
-                let std_functions = program.std_commands.clone();
-                if std_functions.contains(id) {
-                    matchcmd!(id, "Function isn't enabled or it can't be evaluated.", {
-                        "return" => {
-                            if let Some(arg) = args.first() {
-                                let value = arg.evaluate(&program, writer).unwrap();
-                                Ok(value)
-                            } else {
-                                Err(anyhow!("Need to return only one value!"))
-                            }
-                        },
-                        "split" => {
-                            if let Some(arg) = args.first() {
-                                let value = arg.evaluate(&program, writer).unwrap();
-                                let mut x = Vec::new();
-                                let mut splitVal = String::from(" ");
-                                if let Some(arg) = args.get(1) {
-                                    splitVal = arg.evaluate(&program, writer).unwrap().as_string();
-                                }
-                                for i in value.as_string().trim().split(splitVal.as_str()) {
-                                    if let Ok(n) = Decimal::from_str(i) {
-                                        x.push(Data::Number(n));
-                                    }
-                                    else {
-                                        match i {
-                                            "true" => {x.push(Data::Bool(true))},
-                                            "false" => {x.push(Data::Bool(false))},
-                                            _ => x.push(Data::String(String::from(i)))
-                                        }
-                                    }
-                                }
-                                return Ok(Data::Array(x))
-                            }
-                            Ok(Data::Number(dec!(1)))
-                        },
-                        "join" => {
-                            if let (Some(left), Some(right)) = (args.get(0), args.get(1)) {
-                                if let (Ok(l), Ok(r)) = (left.evaluate(&program, writer), right.evaluate(&program, writer)) {
-                                    match (l, r) {
-                                        (Data::Array(l), Data::Array(r)) => {return Ok(Data::Array([l, r].concat()))}
-                                        _ => panic!("Both argument needs to be an array!")
-                                    }
-                                }
-                                else {
-                                    panic!("Unable to parse!")
-                                }
-                            } else {
-                                panic!("Not enough argument!")
-                            }
-                        },
-                        "input" => {
-                            let mut userInput = String::new();
-                            let stdin = std::io::stdin();
-                            stdin.read_line(&mut userInput);
-                            Ok(Data::String(userInput))
-                        },
-                        "inputf" => {
-                            if let Some(format_arg) = args.first() {
-                                let format_string = format_arg.evaluate(&program, writer).unwrap().as_string();
-                                let mut user_input = String::new();
-
-                                // Read user input
-                                let stdin = std::io::stdin();
-                                stdin.read_line(&mut user_input).expect("Failed to read user input");
-
-                                // Split the format string into individual format specifiers
-                                let format_specifiers: Vec<&str> = format_string.trim().split(' ').collect();
-
-                                // Split the user input based on spaces and trim any leading/trailing whitespaces
-                                let user_values: Vec<&str> = user_input.trim().split(' ').collect();
-
-                                // Check if the number of format specifiers matches the number of user input values
-                                if format_specifiers.len() != user_values.len() {
-                                    return Err(anyhow!("Input does not match the specified format"));
-                                }
-
-                                // Convert user input values to the corresponding Data types based on format specifiers
-                                let mut result = Vec::new();
-                                for (i, &format_specifier) in format_specifiers.iter().enumerate() {
-                                    match format_specifier {
-                                        "%Number" => {
-                                            if let Ok(number) = Decimal::from_str(user_values[i]) {
-                                                result.push(Data::Number(number));
-                                            } else {
-                                                return Err(anyhow!("Invalid number format"));
-                                            }
-                                        },
-                                        "%String" => {
-                                            result.push(Data::String(String::from(user_values[i])));
-                                        },
-                                        "%Bool" => {
-                                            if let Ok(boolean) = bool::from_str(user_values[i]) {
-                                                result.push(Data::Bool(boolean));
-                                            } else {
-                                                return Err(anyhow!("Invalid boolean format"));
-                                            }
-                                        },
-                                        _ => {
-                                            return Err(anyhow!("Invalid format specifier: {}", format_specifier));
-                                        }
-                                    }
-                                }
-
-                                return Ok(Data::Array(result));
-                            }
-                            Ok(Data::Number(dec!(1)))
-                        }
-                    }
-                    )
-                } else if let Some(func) = program.function.get(id) {
-                    match func {
-                        parser::Ast::FunctionDefinition { params, body, .. } => {
-                            if params.len() < args.len() {
-                                panic!("Too many argument!",);
-                            }
-                            if params.len() > args.len() {
-                                panic!("Not enough argument!",);
-                            }
-                            let mut local_variables = HashMap::new();
-                            for (i, arg) in args.iter().enumerate() {
-                                let (name, dtype) = &params[i];
-                                let value = arg.evaluate(program, writer).unwrap();
-                                match dtype.as_str() {
-                                        "Number" => {
-                                            if let Data::Number(_) = value {
-                                            } else {
-                                                panic!("Wrong type for function: expected {}!", dtype);
-                                            }
-                                        }
-                                        "String" => {
-                                            if let Data::String(_) = value {
-                                            } else {
-                                                panic!("Wrong type for function: expected {}!", dtype);
-                                            }
-                                        }
-                                        "Array" => {
-                                            if let Data::Array(_) = value {
-                                            } else {
-                                                panic!("Wrong type for function: expected {}!", dtype);
-                                            }
-                                        }
-                                        "Bool" => {
-                                            if let Data::Bool(_) = value {
-                                            } else {
-                                                panic!("Wrong type for function: expected {}!", dtype);
-                                            }
-                                        }
-                                        _ => panic!("Type does not exist: {}! Only types exist are Integer, String, Bool and Array", dtype),
-                                    }
-                                local_variables.insert(name.clone(), value);
-                            }
-                            let mut program = Program {
-                                commands: *body.clone(),
-                                current_line: 0,
-                                variable: local_variables,
-                                function: program.function.clone(),
-                                std_commands: program.std_commands.clone(),
-                            };
-                            let returncode = program.run_loop(writer, &mut Shell::new());
-                            if let Ok(ret) = returncode {
-                                match ret {
-                                    ReturnType::None => Ok(Data::Number(dec!(0))),
-                                    ReturnType::Ok(red) => Ok(red),
-                                }
-                            } else {
-                                panic!("Function `{}` panicked!", id);
-                            }
-                        }
-                        _ => panic!("`{}` is not a function!", id),
-                    }
-                } else {
-                    panic!("Function `{}` is not defined!", id);
-                }
+                let array = variables
+                    .get(id)
+                    .ok_or_else(|| RuntimeError::UndefinedVariable { name: id.clone() })?;
+                index_array(array.clone(), k.evaluate(program)?)
             }
-            _ => panic!("Invalid AST node"),
+            parser::Ast::FunctionCall { id, args } => program.evaluate_call(id, args),
+            other => Err(anyhow!("Error: {:?} is not an expression", other)),
         }
     }
 }
@@ -0,0 +1,88 @@
+//! Working with arrays
+
+use super::NativeRegistry;
+#[cfg(any(feature = "join", feature = "range"))]
+use super::{Arity, NativeContext, NativeFunction};
+#[cfg(feature = "range")]
+use crate::program::NumberRange;
+#[cfg(any(feature = "join", feature = "range"))]
+use crate::program::{Data, DataType};
+#[cfg(any(feature = "join", feature = "range"))]
+use anyhow::Result;
+#[cfg(feature = "range")]
+use rust_decimal_macros::dec;
+
+// `join` is opt-in, see `NativeRegistry::enable`
+#[cfg_attr(not(feature = "range"), allow(unused_variables))]
+pub(super) fn register(registry: &mut NativeRegistry) {
+    #[cfg(feature = "range")]
+    registry.register(Range);
+}
+
+#[cfg(feature = "join")]
+pub struct Join;
+
+#[cfg(feature = "join")]
+impl NativeFunction for Join {
+    fn name(&self) -> &str {
+        "join"
+    }
+    fn arity(&self) -> Arity {
+        Arity::exact(2)
+    }
+    fn param_types(&self) -> &[DataType] {
+        &[DataType::Array, DataType::Array]
+    }
+    fn return_type(&self) -> DataType {
+        DataType::Array
+    }
+    fn docs(&self) -> &str {
+        "join(left, right): returns a new Array with the elements of `left` then `right`"
+    }
+    fn call(&self, _ctx: &mut NativeContext, args: Vec<Data>) -> Result<Data> {
+        let mut args = args.into_iter();
+        match (args.next(), args.next()) {
+            (Some(Data::Array(l)), Some(Data::Array(r))) => {
+                Ok(Data::from([l.as_slice(), r.as_slice()].concat()))
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(feature = "range")]
+pub struct Range;
+
+#[cfg(feature = "range")]
+impl NativeFunction for Range {
+    fn name(&self) -> &str {
+        "range"
+    }
+    fn arity(&self) -> Arity {
+        Arity::between(1, 3)
+    }
+    fn param_types(&self) -> &[DataType] {
+        &[DataType::Number]
+    }
+    fn return_type(&self) -> DataType {
+        DataType::Array
+    }
+    fn docs(&self) -> &str {
+        "range(start, stop, step): returns an Array of numbers from `start` (0 if only `stop` \
+         is given) up to but not including `stop`, `step` (1 if not given) apart"
+    }
+    fn call(&self, ctx: &mut NativeContext, args: Vec<Data>) -> Result<Data> {
+        let bounds = args
+            .iter()
+            .map(Data::as_number)
+            .collect::<Result<Vec<_>>>()?;
+        let (start, stop, step) = match bounds[..] {
+            [stop] => (dec!(0), stop, dec!(1)),
+            [start, stop] => (start, stop, dec!(1)),
+            [start, stop, step] => (start, stop, step),
+            _ => unreachable!(),
+        };
+        ctx.limits
+            .collect(NumberRange::new(start, stop, step, false)?.map(Data::Number))
+    }
+}
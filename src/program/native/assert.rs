@@ -0,0 +1,189 @@
+//! Checking that code does what it should, for `teo test`
+
+use super::NativeRegistry;
+#[cfg(feature = "assert")]
+use super::{Arity, NativeContext, NativeFunction};
+#[cfg(feature = "assert")]
+use crate::program::{Data, DataType};
+#[cfg(feature = "assert")]
+use anyhow::Result;
+#[cfg(feature = "assert")]
+use rust_decimal_macros::dec;
+use std::fmt;
+
+#[cfg_attr(not(feature = "assert"), allow(unused_variables))]
+pub(super) fn register(registry: &mut NativeRegistry) {
+    #[cfg(feature = "assert")]
+    registry.register(Assert);
+    #[cfg(feature = "assert")]
+    registry.register(AssertEq);
+}
+
+/// Error `assert` and `assert_eq` stop the program with. Use `anyhow::Error::downcast_ref` to
+/// find it, for example to show how the values differ.
+///
+/// ```rust
+/// # use teolang::program::{native::AssertionFailed, Program};
+/// let mut program = Program::builder()
+///     .source("assert_eq([1, [2, 3]], [1, [2, 4], 5]);")
+///     .build()?;
+/// let error = program.run().err().unwrap();
+/// let failed = error.downcast_ref::<AssertionFailed>().unwrap();
+/// assert_eq!(
+///     failed.to_string(),
+///     "Assertion failed: [1, [2, 3]] is not [1, [2, 4], 5]"
+/// );
+/// assert_eq!(failed.differences, ["length: 2 vs 3", "[1][1]: 3 vs 4"]);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssertionFailed {
+    /// Message given to the assertion, if any
+    pub message: Option<String>,
+    /// The two values `assert_eq` compared, written as in Teo code
+    pub values: Option<(String, String)>,
+    /// Where inside those values they differ: the length and elements of arrays, the lines of
+    /// strings
+    pub differences: Vec<String>,
+}
+
+#[cfg(feature = "assert")]
+impl AssertionFailed {
+    fn new(message: Option<String>, left: &Data, right: &Data) -> AssertionFailed {
+        let mut differences = Vec::new();
+        diff("", left, right, &mut differences);
+        AssertionFailed {
+            message,
+            values: Some((left.as_code(), right.as_code())),
+            differences,
+        }
+    }
+}
+
+/// Adds to `differences` where `left` and `right`, found at `path`, differ
+#[cfg(feature = "assert")]
+fn diff(path: &str, left: &Data, right: &Data, differences: &mut Vec<String>) {
+    let at = |what: &str| match (path, what) {
+        ("", what) => what.to_string(),
+        (path, "") => path.to_string(),
+        (path, what) => format!("{} {}", path, what),
+    };
+    match (left, right) {
+        (Data::Array(l), Data::Array(r)) => {
+            if l.len() != r.len() {
+                differences.push(format!("{}: {} vs {}", at("length"), l.len(), r.len()));
+            }
+            for (i, (l, r)) in l.iter().zip(r.iter()).enumerate() {
+                if l != r {
+                    diff(&format!("{}[{}]", path, i), l, r, differences);
+                }
+            }
+        }
+        (Data::String(l), Data::String(r)) if l.contains('\n') || r.contains('\n') => {
+            let (l, r): (Vec<_>, Vec<_>) = (l.lines().collect(), r.lines().collect());
+            for i in 0..l.len().max(r.len()) {
+                let (l, r) = (l.get(i), r.get(i));
+                if l != r {
+                    let line = |line: Option<&&str>| {
+                        line.map_or("nothing".to_string(), |l| format!("{:?}", l))
+                    };
+                    differences.push(format!(
+                        "{}: {} vs {}",
+                        at(&format!("line {}", i + 1)),
+                        line(l),
+                        line(r)
+                    ));
+                }
+            }
+        }
+        _ if !path.is_empty() => differences.push(format!(
+            "{}: {} vs {}",
+            at(""),
+            left.as_code(),
+            right.as_code()
+        )),
+        _ => {}
+    }
+}
+
+impl fmt::Display for AssertionFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Assertion failed")?;
+        if let Some(message) = &self.message {
+            write!(f, ": {}", message)?;
+        } else if let Some((left, right)) = &self.values {
+            write!(f, ": {} is not {}", left, right)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for AssertionFailed {}
+
+#[cfg(feature = "assert")]
+pub struct Assert;
+
+#[cfg(feature = "assert")]
+impl NativeFunction for Assert {
+    fn name(&self) -> &str {
+        "assert"
+    }
+    fn arity(&self) -> Arity {
+        Arity::between(1, 2)
+    }
+    fn param_types(&self) -> &[DataType] {
+        &[DataType::Bool, DataType::Any]
+    }
+    fn return_type(&self) -> DataType {
+        DataType::Number
+    }
+    fn docs(&self) -> &str {
+        "assert(condition, message): stops the program with `message` if `condition` is false"
+    }
+    fn call(&self, _ctx: &mut NativeContext, args: Vec<Data>) -> Result<Data> {
+        match args[0] {
+            Data::Bool(true) => Ok(Data::Number(dec!(0))),
+            _ => Err(AssertionFailed {
+                message: Some(
+                    args.get(1)
+                        .map_or_else(|| "condition is false".to_string(), Data::as_string),
+                ),
+                values: None,
+                differences: Vec::new(),
+            }
+            .into()),
+        }
+    }
+}
+
+#[cfg(feature = "assert")]
+pub struct AssertEq;
+
+#[cfg(feature = "assert")]
+impl NativeFunction for AssertEq {
+    fn name(&self) -> &str {
+        "assert_eq"
+    }
+    fn arity(&self) -> Arity {
+        Arity::between(2, 3)
+    }
+    fn param_types(&self) -> &[DataType] {
+        &[DataType::Any]
+    }
+    fn return_type(&self) -> DataType {
+        DataType::Number
+    }
+    fn docs(&self) -> &str {
+        "assert_eq(left, right, message): stops the program (with `message` if given) unless \
+         `left` and `right` are equal"
+    }
+    fn call(&self, _ctx: &mut NativeContext, args: Vec<Data>) -> Result<Data> {
+        let mut args = args.into_iter();
+        let (left, right) = (args.next().unwrap(), args.next().unwrap());
+        if left == right {
+            return Ok(Data::Number(dec!(0)));
+        }
+        let message = args.next().map(|message| message.as_string());
+        Err(AssertionFailed::new(message, &left, &right).into())
+    }
+}
@@ -0,0 +1,68 @@
+//! What the program gets from outside: command line arguments and environment variables
+
+use super::NativeRegistry;
+#[cfg(any(feature = "args", feature = "env"))]
+use super::{Arity, NativeContext, NativeFunction};
+#[cfg(any(feature = "args", feature = "env"))]
+use crate::program::{Data, DataType};
+#[cfg(any(feature = "args", feature = "env"))]
+use anyhow::Result;
+
+// `env` is opt-in, see `NativeRegistry::enable`
+#[cfg_attr(not(feature = "args"), allow(unused_variables))]
+pub(super) fn register(registry: &mut NativeRegistry) {
+    #[cfg(feature = "args")]
+    registry.register(Args);
+}
+
+#[cfg(feature = "args")]
+pub struct Args;
+
+#[cfg(feature = "args")]
+impl NativeFunction for Args {
+    fn name(&self) -> &str {
+        "args"
+    }
+    fn arity(&self) -> Arity {
+        Arity::exact(0)
+    }
+    fn param_types(&self) -> &[DataType] {
+        &[]
+    }
+    fn return_type(&self) -> DataType {
+        DataType::Array
+    }
+    fn docs(&self) -> &str {
+        "args(): returns the arguments given after the file name (`teo run grade.teo 42`) as \
+         an Array of Strings"
+    }
+    fn call(&self, ctx: &mut NativeContext, _args: Vec<Data>) -> Result<Data> {
+        Ok(Data::from(ctx.args.to_vec()))
+    }
+}
+
+#[cfg(feature = "env")]
+pub struct Env;
+
+#[cfg(feature = "env")]
+impl NativeFunction for Env {
+    fn name(&self) -> &str {
+        "env"
+    }
+    fn arity(&self) -> Arity {
+        Arity::exact(1)
+    }
+    fn param_types(&self) -> &[DataType] {
+        &[DataType::String]
+    }
+    fn return_type(&self) -> DataType {
+        DataType::String
+    }
+    fn docs(&self) -> &str {
+        "env(name): returns the environment variable `name`, or an empty String if it is not set"
+    }
+    fn call(&self, _ctx: &mut NativeContext, args: Vec<Data>) -> Result<Data> {
+        let name = args[0].as_string();
+        Ok(Data::from(std::env::var(name).unwrap_or_default()))
+    }
+}
@@ -0,0 +1,153 @@
+//! Reading and printing
+
+use super::NativeRegistry;
+#[cfg(any(feature = "print", feature = "input", feature = "inputf"))]
+use super::{Arity, NativeContext, NativeFunction};
+#[cfg(any(feature = "print", feature = "input", feature = "inputf"))]
+use crate::program::{Data, DataType};
+#[cfg(feature = "inputf")]
+use anyhow::anyhow;
+#[cfg(any(feature = "print", feature = "input", feature = "inputf"))]
+use anyhow::Result;
+#[cfg(feature = "inputf")]
+use rust_decimal::prelude::*;
+#[cfg(feature = "print")]
+use rust_decimal_macros::dec;
+
+#[cfg_attr(
+    not(any(feature = "print", feature = "input", feature = "inputf")),
+    allow(unused_variables)
+)]
+pub(super) fn register(registry: &mut NativeRegistry) {
+    #[cfg(feature = "print")]
+    registry.register(Print);
+    #[cfg(feature = "input")]
+    registry.register(Input);
+    #[cfg(feature = "inputf")]
+    registry.register(InputF);
+}
+
+#[cfg(feature = "print")]
+pub struct Print;
+
+#[cfg(feature = "print")]
+impl NativeFunction for Print {
+    fn name(&self) -> &str {
+        "print"
+    }
+    fn arity(&self) -> Arity {
+        Arity::at_least(0)
+    }
+    fn param_types(&self) -> &[DataType] {
+        &[DataType::Any]
+    }
+    fn return_type(&self) -> DataType {
+        DataType::Number
+    }
+    fn docs(&self) -> &str {
+        "print(values...): prints every value on its own line"
+    }
+    fn call(&self, ctx: &mut NativeContext, args: Vec<Data>) -> Result<Data> {
+        for value in args {
+            writeln!(ctx.output, "{}", value.as_string())?;
+        }
+        Ok(Data::Number(dec!(0)))
+    }
+}
+
+#[cfg(feature = "input")]
+pub struct Input;
+
+#[cfg(feature = "input")]
+impl NativeFunction for Input {
+    fn name(&self) -> &str {
+        "input"
+    }
+    fn arity(&self) -> Arity {
+        Arity::exact(0)
+    }
+    fn param_types(&self) -> &[DataType] {
+        &[]
+    }
+    fn return_type(&self) -> DataType {
+        DataType::String
+    }
+    fn docs(&self) -> &str {
+        "input(): reads one line and returns it as a String"
+    }
+    fn call(&self, ctx: &mut NativeContext, _args: Vec<Data>) -> Result<Data> {
+        let mut user_input = String::new();
+        ctx.input.read_line(&mut user_input)?;
+        Ok(Data::String(user_input.into()))
+    }
+}
+
+#[cfg(feature = "inputf")]
+pub struct InputF;
+
+#[cfg(feature = "inputf")]
+impl NativeFunction for InputF {
+    fn name(&self) -> &str {
+        "inputf"
+    }
+    fn arity(&self) -> Arity {
+        Arity::exact(1)
+    }
+    fn param_types(&self) -> &[DataType] {
+        &[DataType::String]
+    }
+    fn return_type(&self) -> DataType {
+        DataType::Array
+    }
+    fn docs(&self) -> &str {
+        "inputf(format): reads one line of space separated values described by `format` \
+         (`%Number`, `%String` or `%Bool` for each value) and returns them as an Array"
+    }
+    fn call(&self, ctx: &mut NativeContext, args: Vec<Data>) -> Result<Data> {
+        let format_string = args[0].as_string();
+        let mut user_input = String::new();
+
+        // Read user input
+        ctx.input.read_line(&mut user_input)?;
+
+        // Split the format string into individual format specifiers
+        let format_specifiers: Vec<&str> = format_string.trim().split(' ').collect();
+
+        // Split the user input based on spaces and trim any leading/trailing whitespaces
+        let user_values: Vec<&str> = user_input.trim().split(' ').collect();
+
+        // Check if the number of format specifiers matches the number of user input values
+        if format_specifiers.len() != user_values.len() {
+            return Err(anyhow!("Input does not match the specified format"));
+        }
+
+        // Convert user input values to the corresponding Data types based on format specifiers
+        let mut result = Vec::new();
+        for (i, &format_specifier) in format_specifiers.iter().enumerate() {
+            match format_specifier {
+                "%Number" => {
+                    if let Ok(number) = Decimal::from_str(user_values[i]) {
+                        result.push(Data::Number(number));
+                    } else {
+                        return Err(anyhow!("Invalid number format"));
+                    }
+                }
+                "%String" => {
+                    result.push(Data::String(user_values[i].into()));
+                }
+                "%Bool" => {
+                    if let Ok(boolean) = bool::from_str(user_values[i]) {
+                        result.push(Data::Bool(boolean));
+                    } else {
+                        return Err(anyhow!("Invalid boolean format"));
+                    }
+                }
+                _ => {
+                    return Err(anyhow!("Invalid format specifier: {}", format_specifier));
+                }
+            }
+        }
+
+        Ok(Data::from(result))
+    }
+}
@@ -0,0 +1,233 @@
+//! Functions implemented in Rust that Teo code can call (`print`, `split`, ...)
+//!
+//! Every function implements [`NativeFunction`] and lives in a [`NativeRegistry`], which
+//! [`Program`](super::Program) looks up before user-defined functions. Embedders can register
+//! their own functions next to the standard ones:
+//!
+//! ```rust
+//! # use teolang::program::native::{Arity, NativeContext, NativeFunction, NativeRegistry};
+//! # use teolang::program::{Data, DataType};
+//! # use anyhow::Result;
+//! struct Shout;
+//!
+//! impl NativeFunction for Shout {
+//!     fn name(&self) -> &str {
+//!         "shout"
+//!     }
+//!     fn arity(&self) -> Arity {
+//!         Arity::exact(1)
+//!     }
+//!     fn param_types(&self) -> &[DataType] {
+//!         &[DataType::String]
+//!     }
+//!     fn docs(&self) -> &str {
+//!         "Returns the string in upper case"
+//!     }
+//!     fn call(&self, _ctx: &mut NativeContext, args: Vec<Data>) -> Result<Data> {
+//!         match &args[0] {
+//!             Data::String(s) => Ok(s.to_uppercase().into()),
+//!             _ => unreachable!(),
+//!         }
+//!     }
+//! }
+//!
+//! let mut natives = NativeRegistry::standard();
+//! natives.register(Shout);
+//! assert!(natives.contains("shout"));
+//! ```
+
+use super::{Data, DataType, Limits, RuntimeError};
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+mod array;
+mod assert;
+mod env;
+mod io;
+mod string;
+
+pub use assert::AssertionFailed;
+
+/// How many arguments a [`NativeFunction`] accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arity {
+    /// Fewest arguments allowed
+    pub min: usize,
+    /// Most arguments allowed ([`None`] for no limit)
+    pub max: Option<usize>,
+}
+
+impl Arity {
+    pub fn exact(n: usize) -> Arity {
+        Arity {
+            min: n,
+            max: Some(n),
+        }
+    }
+
+    pub fn between(min: usize, max: usize) -> Arity {
+        Arity {
+            min,
+            max: Some(max),
+        }
+    }
+
+    pub fn at_least(min: usize) -> Arity {
+        Arity { min, max: None }
+    }
+
+    pub fn accepts(&self, count: usize) -> bool {
+        count >= self.min && self.max.is_none_or(|max| count <= max)
+    }
+}
+
+/// What a [`NativeFunction`] may use from the running program
+pub struct NativeContext<'a> {
+    /// Where the program reads input from
+    pub input: &'a mut dyn std::io::BufRead,
+    /// Where the program output goes
+    pub output: &'a mut dyn std::io::Write,
+    /// Limits of the program; functions building large values should respect
+    /// [`Limits::max_value_size`] while building them
+    pub limits: &'a Limits,
+    /// Arguments given to the program on the command line
+    pub args: &'a [String],
+}
+
+/// A function implemented in Rust
+pub trait NativeFunction {
+    /// Name Teo code calls the function by
+    fn name(&self) -> &str;
+    /// Number of arguments accepted
+    fn arity(&self) -> Arity;
+    /// Type of each parameter. Arguments past the end of the list use the last type.
+    fn param_types(&self) -> &[DataType];
+    /// Type of the value returned, for `teo check`. [`DataType::Any`] if it depends on the
+    /// arguments.
+    fn return_type(&self) -> DataType {
+        DataType::Any
+    }
+    /// Short description shown to users
+    fn docs(&self) -> &str;
+    /// Runs the function. Arguments have already been checked against [`NativeFunction::arity`]
+    /// and [`NativeFunction::param_types`].
+    fn call(&self, ctx: &mut NativeContext, args: Vec<Data>) -> Result<Data>;
+}
+
+/// Checks `args` against the arity and parameter types of `function`
+pub fn check_args(function: &dyn NativeFunction, args: &[Data]) -> Result<()> {
+    let arity = function.arity();
+    if !arity.accepts(args.len()) {
+        let message = match arity.max {
+            Some(max) if max == arity.min => format!(
+                "`{}` takes {} argument(s), got {}!",
+                function.name(),
+                max,
+                args.len()
+            ),
+            Some(max) => format!(
+                "`{}` takes {} to {} arguments, got {}!",
+                function.name(),
+                arity.min,
+                max,
+                args.len()
+            ),
+            None => format!(
+                "`{}` takes at least {} argument(s), got {}!",
+                function.name(),
+                arity.min,
+                args.len()
+            ),
+        };
+        return Err(RuntimeError::WrongArguments { message }.into());
+    }
+    let types = function.param_types();
+    for (i, arg) in args.iter().enumerate() {
+        if let Some(expected) = types.get(i).or(types.last()) {
+            if !expected.matches(arg) {
+                return Err(RuntimeError::WrongArguments {
+                    message: format!(
+                        "Wrong type for function `{}`: expected {} for argument {}, got {:?}!",
+                        function.name(),
+                        expected,
+                        i + 1,
+                        arg
+                    ),
+                }
+                .into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Set of [`NativeFunction`]s a [`Program`](super::Program) can call
+#[derive(Clone, Default)]
+pub struct NativeRegistry {
+    functions: HashMap<String, Rc<dyn NativeFunction>>,
+}
+
+impl NativeRegistry {
+    /// Creates a registry without any function
+    pub fn new() -> NativeRegistry {
+        NativeRegistry::default()
+    }
+
+    /// Creates a registry with every standard function that is enabled by default
+    pub fn standard() -> NativeRegistry {
+        let mut registry = NativeRegistry::new();
+        io::register(&mut registry);
+        string::register(&mut registry);
+        array::register(&mut registry);
+        env::register(&mut registry);
+        assert::register(&mut registry);
+        registry
+    }
+
+    /// Enables an opt-in standard function (like `join` or `env`) by its feature name. Features that
+    /// are already enabled are accepted as well.
+    pub fn enable(&mut self, feature: &str) -> Result<()> {
+        if self.contains(feature) {
+            return Ok(());
+        }
+        match feature {
+            #[cfg(feature = "join")]
+            "join" => {
+                self.register(array::Join);
+                Ok(())
+            }
+            #[cfg(feature = "env")]
+            "env" => {
+                self.register(env::Env);
+                Ok(())
+            }
+            _ => Err(anyhow!("Function {} is not enabled!", feature)),
+        }
+    }
+
+    /// Adds a function, replacing any function with the same name
+    pub fn register(&mut self, function: impl NativeFunction + 'static) {
+        self.functions
+            .insert(function.name().to_string(), Rc::new(function));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Rc<dyn NativeFunction>> {
+        self.functions.get(name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    /// Names of every registered function, sorted
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.functions.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Rc<dyn NativeFunction>> {
+        self.functions.values()
+    }
+}
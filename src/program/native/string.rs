@@ -0,0 +1,59 @@
+//! Working with strings
+
+use super::NativeRegistry;
+#[cfg(feature = "split")]
+use super::{Arity, NativeContext, NativeFunction};
+#[cfg(feature = "split")]
+use crate::program::{Data, DataType};
+#[cfg(feature = "split")]
+use anyhow::Result;
+#[cfg(feature = "split")]
+use rust_decimal::prelude::*;
+
+#[cfg_attr(not(feature = "split"), allow(unused_variables))]
+pub(super) fn register(registry: &mut NativeRegistry) {
+    #[cfg(feature = "split")]
+    registry.register(Split);
+}
+
+#[cfg(feature = "split")]
+pub struct Split;
+
+#[cfg(feature = "split")]
+impl NativeFunction for Split {
+    fn name(&self) -> &str {
+        "split"
+    }
+    fn arity(&self) -> Arity {
+        Arity::between(1, 2)
+    }
+    fn param_types(&self) -> &[DataType] {
+        &[DataType::Any, DataType::Any]
+    }
+    fn return_type(&self) -> DataType {
+        DataType::Array
+    }
+    fn docs(&self) -> &str {
+        "split(value, separator): splits `value` at every `separator` (a space if not given) \
+         and returns the pieces as an Array, turning numbers and bools into their own type"
+    }
+    fn call(&self, _ctx: &mut NativeContext, args: Vec<Data>) -> Result<Data> {
+        let mut x = Vec::new();
+        let split_val = match args.get(1) {
+            Some(arg) => arg.as_string(),
+            None => String::from(" "),
+        };
+        for i in args[0].as_string().trim().split(split_val.as_str()) {
+            if let Ok(n) = Decimal::from_str(i) {
+                x.push(Data::Number(n));
+            } else {
+                match i {
+                    "true" => x.push(Data::Bool(true)),
+                    "false" => x.push(Data::Bool(false)),
+                    _ => x.push(Data::String(i.into())),
+                }
+            }
+        }
+        Ok(Data::from(x))
+    }
+}
@@ -0,0 +1,160 @@
+//! Optional pass over [`Ast`] run before a program starts: folds operators whose operands
+//! are literals, drops statements after `return` and removes `if` blocks whose condition is
+//! a literal.
+//!
+//! ```rust
+//! # use teolang::program::{optimize::optimize, parser::Ast};
+//! let ast = optimize(Ast::parse_code("print(3 + 4 * 2); return(0); print(1);")?);
+//! let expected = Ast::parse_code("print(11); return(0);")?;
+//! assert_eq!(ast, expected);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use super::parser::{Ast, Statement};
+use super::{binary_op, Data};
+use std::rc::Rc;
+
+/// Optimises a whole program (an [`Ast::Block`]); other nodes are optimised as expressions
+pub fn optimize(ast: Ast) -> Ast {
+    match ast {
+        Ast::Block(statements) => Ast::Block(block(statements)),
+        expr => expression(expr),
+    }
+}
+
+fn block(statements: Vec<Statement>) -> Vec<Statement> {
+    let mut optimized = Vec::with_capacity(statements.len());
+    for Statement { line, ast } in statements {
+        let returns = ast.is_return();
+        match ast {
+            Ast::If {
+                condition,
+                block: body,
+            } => match expression(*condition) {
+                Ast::Bool(false) => {}
+                // The block can only be inlined if it does not define functions, which are
+                // forgotten when the block ends
+                Ast::Bool(true) if !defines_functions(&body) => {
+                    let inlined = match *body {
+                        Ast::Block(statements) => block(statements),
+                        other => vec![Statement { line, ast: other }],
+                    };
+                    let inlined_return = inlined.last().is_some_and(|s| s.ast.is_return());
+                    optimized.extend(inlined);
+                    if inlined_return {
+                        break;
+                    }
+                }
+                condition => optimized.push(Statement {
+                    line,
+                    ast: Ast::If {
+                        condition: Box::new(condition),
+                        block: Box::new(optimize(*body)),
+                    },
+                }),
+            },
+            ast => optimized.push(Statement {
+                line,
+                ast: statement(ast),
+            }),
+        }
+        // Nothing after `return` runs
+        if returns {
+            break;
+        }
+    }
+    optimized
+}
+
+fn defines_functions(block: &Ast) -> bool {
+    match block {
+        Ast::Block(statements) => statements
+            .iter()
+            .any(|s| matches!(s.ast, Ast::FunctionDefinition { .. })),
+        _ => false,
+    }
+}
+
+fn statement(statement: Ast) -> Ast {
+    match statement {
+        Ast::Set { id, op, expr } => Ast::Set {
+            id: Box::new(expression(*id)),
+            op,
+            expr: Box::new(expression(*expr)),
+        },
+        Ast::FunctionDefinition { id, params, body } => Ast::FunctionDefinition {
+            id,
+            params,
+            body: Box::new(optimize(*body)),
+        },
+        Ast::ForLoop {
+            element,
+            elements,
+            block,
+        } => Ast::ForLoop {
+            element,
+            elements: Box::new(expression(*elements)),
+            block: Box::new(optimize(*block)),
+        },
+        expr => expression(expr),
+    }
+}
+
+fn expression(expr: Ast) -> Ast {
+    let boxed = |expr: Box<Ast>| Box::new(expression(*expr));
+    match expr {
+        Ast::BinaryOp { op, left, right } => {
+            let left = expression(*left);
+            let right = expression(*right);
+            match fold(&op, &left, &right) {
+                Some(folded) => folded,
+                None => Ast::BinaryOp {
+                    op,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                },
+            }
+        }
+        Ast::Interpolated(parts) => Ast::Interpolated(parts.into_iter().map(expression).collect()),
+        Ast::FunctionCall { id, args } => Ast::FunctionCall {
+            id,
+            args: args.into_iter().map(expression).collect(),
+        },
+        Ast::Array(elements) => Ast::Array(elements.into_iter().map(expression).collect()),
+        Ast::ArrayAccess { expr, whereto } => Ast::ArrayAccess {
+            expr: Rc::new(expression(Rc::unwrap_or_clone(expr))),
+            whereto: boxed(whereto),
+        },
+        Ast::AstSlice { from, to } => Ast::AstSlice {
+            from: from.map(boxed),
+            to: to.map(boxed),
+        },
+        Ast::Range {
+            start,
+            end,
+            step,
+            inclusive,
+        } => Ast::Range {
+            start: boxed(start),
+            end: boxed(end),
+            step: step.map(boxed),
+            inclusive,
+        },
+        other => other,
+    }
+}
+
+/// Computes `left op right` if both are literals. Operators that would fail are left for
+/// the program to fail on when it runs.
+fn fold(op: &str, left: &Ast, right: &Ast) -> Option<Ast> {
+    let literal = |ast: &Ast| match ast {
+        Ast::Int(n) => Some(Data::Number(*n)),
+        Ast::Bool(b) => Some(Data::Bool(*b)),
+        _ => None,
+    };
+    match binary_op(op, &literal(left)?, &literal(right)?).ok()? {
+        Data::Number(n) => Some(Ast::Int(n)),
+        Data::Bool(b) => Some(Ast::Bool(b)),
+        _ => None,
+    }
+}
@@ -5,6 +5,7 @@ use pest::{
 };
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::rc::Rc;
 
@@ -18,20 +19,47 @@ struct MyParser;
 
 use anyhow::{anyhow, Result};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// Version of the JSON form of [`Ast`], and of every JSON line `teo` writes. It goes up
+/// whenever that form changes, so tools reading `teo --format json ast` can tell which one
+/// they got.
+pub const JSON_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value")]
 /// Abstract syntax tree type
 /// Should have tree structure but I'm stupid so not now
+///
+/// In JSON every node is an object with its variant name as `type` and its fields (if any)
+/// as `value`; the statements of a block also have their `line`. Numbers are written as
+/// strings so that no digit is lost:
+/// ```rust
+/// # use teolang::program::parser::Ast;
+/// let ast = Ast::parse_code("x = 1 + y;")?;
+/// let json = serde_json::to_string(&ast)?;
+/// assert_eq!(
+///     json,
+///     r#"{"type":"Block","value":[{"line":1,"type":"Set","value":{"id":{"type":"Identifier","value":"x"},"op":null,"expr":{"type":"BinaryOp","value":{"op":"+","left":{"type":"Int","value":"1"},"right":{"type":"Identifier","value":"y"}}}}}]}"#
+/// );
+/// assert_eq!(serde_json::from_str::<Ast>(&json)?, ast);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
 pub enum Ast {
     /// Code block
     Block(
         /// Map of the block
-        Vec<Ast>,
+        Vec<Statement>,
     ),
     /// String data type
     String(String),
+    /// String with `{expr}` placeholders (`"Hello {name}!"`)
+    Interpolated(
+        /// Pieces to join, either [`Ast::String`] or an expression
+        Vec<Ast>,
+    ),
     /// Decimal data type (a bit of misleading name)
     Int(Decimal),
-    /// All identifiers (a-Z). Used in function name, variable name, etc.
+    /// All identifiers: letters, digits and `_`, not starting with a digit. Used in function
+    /// name, variable name, etc.
     Identifier(String),
     /// Binary operation
     /// (Can be nested)
@@ -53,10 +81,12 @@ pub enum Ast {
         /// Right expression
         right: Box<Ast>,
     },
-    /// Set expression (id = expr)
+    /// Set expression (id = expr, id += expr, ...)
     Set {
         /// Identifier to set value to
         id: Box<Ast>,
+        /// Operator of a compound assignment (`Some("+")` for `+=`), [`None`] for `=`
+        op: Option<String>,
         /// Expression of value to set
         expr: Box<Ast>,
     },
@@ -108,71 +138,108 @@ pub enum Ast {
         /// The code block that will run if condition evaluated to true
         block: Box<Ast>,
     },
+    /// Range of numbers (`start..end`, `start..=end`, `start..end step n`)
+    Range {
+        /// First number of the range
+        start: Box<Ast>,
+        /// Where the range stops
+        end: Box<Ast>,
+        /// Distance between two numbers (1 if not given)
+        step: Option<Box<Ast>>,
+        /// Whether `end` is part of the range (`..=`)
+        inclusive: bool,
+    },
     ForLoop {
         element: Box<Ast>,
         elements: Box<Ast>,
         block: Box<Ast>,
     },
     Fac(Box<Ast>),
+    /// `// comment`, on its own line or after a statement, kept so that `teo fmt` can write it
+    /// back
+    Comment(
+        /// Text after the `//`
+        String,
+    ),
+}
+
+/// One statement of an [`Ast::Block`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Statement {
+    /// Line (starting at 1) the statement starts on, 0 for code that was not parsed
+    pub line: usize,
+    #[serde(flatten)]
+    pub ast: Ast,
+}
+
+impl From<Ast> for Statement {
+    /// A statement without a line
+    fn from(ast: Ast) -> Self {
+        Statement { line: 0, ast }
+    }
 }
 
+/// Formats the node as Teo code, see [`format`](super::format)
 impl fmt::Display for Ast {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Ast::String(s) => write!(f, "\"{}\"", s),
-            Ast::Int(n) => write!(f, "{}", n),
-            Ast::Bool(k) => write!(f, "{}", if *k { "true" } else { "false" }),
-            Ast::Array(elements) => {
-                write!(f, "[")?;
-                for (i, element) in elements.iter().enumerate() {
-                    write!(f, "{}", element)?;
-                    if i < elements.len() - 1 {
-                        write!(f, ", ")?;
-                    }
-                }
-                write!(f, "]")
-            }
-            Ast::Identifier(s) => write!(f, "{}", s),
-            _ => {
-                unimplemented!()
-            }
-        }
+        write!(f, "{}", super::format::expression(self))
     }
 }
 
-fn parse_string(s: pest::iterators::Pair<'_, Rule>) -> String {
+fn parse_string(s: pest::iterators::Pair<'_, Rule>, pratt: &PrattParser<Rule>) -> Ast {
+    let mut parts = vec![];
     let mut str = "".to_string();
     for i in s.into_inner() {
         match i.as_rule() {
-            Rule::raw_string => str = str + i.as_str(),
+            Rule::raw_string => str += i.as_str(),
+            Rule::predefined => str.push(match i.as_str() {
+                "n" => '\n',
+                "r" => '\r',
+                "t" => '\t',
+                "0" => '\0',
+                other => other.chars().next().unwrap(),
+            }),
             Rule::unicode_hex => {
-                str = str
-                    + std::char::from_u32(u32::from_str_radix(i.as_str(), 16).unwrap())
-                        .unwrap()
-                        .to_string()
-                        .as_str()
+                str += std::char::from_u32(u32::from_str_radix(i.as_str(), 16).unwrap())
+                    .unwrap()
+                    .to_string()
+                    .as_str()
             }
             Rule::byte => {
-                str = str
-                    + (i.as_str().parse::<u8>().expect("To parse") as char)
-                        .to_string()
-                        .as_str()
+                str += (i.as_str().parse::<u8>().expect("To parse") as char)
+                    .to_string()
+                    .as_str()
+            }
+            // `{{` and `}}` stand for a literal brace
+            Rule::brace_escape => str.push_str(&i.as_str()[..1]),
+            Rule::interpolation => {
+                if !str.is_empty() {
+                    parts.push(Ast::String(std::mem::take(&mut str)));
+                }
+                let expr = i.into_inner().next().unwrap();
+                parts.push(parse_expr(expr.into_inner(), pratt));
             }
             _ => unimplemented!("{:?}", i),
         };
     }
-    str
+    if parts.is_empty() {
+        return Ast::String(str);
+    }
+    if !str.is_empty() {
+        parts.push(Ast::String(str));
+    }
+    Ast::Interpolated(parts)
 }
 
 fn handle_arr(primary: Pair<'_, Rule>, pratt: &PrattParser<Rule>) -> Ast {
     let mut varindex: Option<Box<Ast>> = None;
     let mut wheretoindex: Option<Box<Ast>> = None;
-    for i in primary.into_inner().into_iter() {
+    for i in primary.into_inner() {
         match i.as_rule() {
             Rule::indexable_expr => varindex = Some(Box::new(parse_expr(i.into_inner(), pratt))),
             Rule::from_to_index => {
                 let mut x: Vec<Ast> = vec![];
-                i.into_inner().into_iter().for_each(|f|
+                i.into_inner().for_each(|f|
                         // x.push(parse_expr(f.into_inner(), pratt))
                         x.push(parse_expr(Pairs::single(f), pratt)));
                 if x.len() != 2 {
@@ -186,7 +253,7 @@ fn handle_arr(primary: Pair<'_, Rule>, pratt: &PrattParser<Rule>) -> Ast {
             }
             Rule::from_index => {
                 let mut x: Vec<Ast> = vec![];
-                i.into_inner().into_iter().for_each(|f|
+                i.into_inner().for_each(|f|
                         // x.push(parse_expr(f.into_inner(), pratt))
                         x.push(parse_expr(Pairs::single(f), pratt)));
                 if x.len() != 1 {
@@ -200,7 +267,7 @@ fn handle_arr(primary: Pair<'_, Rule>, pratt: &PrattParser<Rule>) -> Ast {
             }
             Rule::to_index => {
                 let mut x: Vec<Ast> = vec![];
-                i.into_inner().into_iter().for_each(|f|
+                i.into_inner().for_each(|f|
                         // x.push(parse_expr(f.into_inner(), pratt))
                         x.push(parse_expr(Pairs::single(f), pratt)));
                 if x.len() != 1 {
@@ -228,9 +295,8 @@ fn handle_arr(primary: Pair<'_, Rule>, pratt: &PrattParser<Rule>) -> Ast {
 
 fn handle_array(primary: Pair<'_, Rule>, pratt: &PrattParser<Rule>) -> Ast {
     let mut x = vec![];
-    primary.into_inner().into_iter().for_each(|f| {
+    primary.into_inner().for_each(|f| {
         f.into_inner()
-            .into_iter()
             .for_each(|j| x.push(parse_expr(j.into_inner(), pratt)))
     });
     Ast::Array(x)
@@ -239,11 +305,11 @@ fn handle_array(primary: Pair<'_, Rule>, pratt: &PrattParser<Rule>) -> Ast {
 fn parse_expr(pairs: Pairs<Rule>, pratt: &PrattParser<Rule>) -> Ast {
     pratt
         .map_primary(|primary| match primary.as_rule() {
-            Rule::int => Ast::Int(Decimal::from_str_exact(&primary.as_str()).unwrap()),
+            Rule::int => Ast::Int(Decimal::from_str_exact(primary.as_str()).unwrap()),
             Rule::expr => parse_expr(primary.into_inner(), pratt), // from "(" ~ expr ~ ")"
             Rule::command => handle_command(primary, pratt),
             Rule::ident => Ast::Identifier(primary.as_str().trim().to_string()),
-            Rule::string => Ast::String(parse_string(primary)),
+            Rule::string => parse_string(primary, pratt),
             Rule::bool => match primary.as_str() {
                 "true" => Ast::Bool(true),
                 "false" => Ast::Bool(false),
@@ -300,38 +366,65 @@ fn parse_expr(pairs: Pairs<Rule>, pratt: &PrattParser<Rule>) -> Ast {
                 left: Box::new(lhs),
                 right: Box::new(rhs),
             },
+            Rule::range_op => Ast::Range {
+                start: Box::new(lhs),
+                end: Box::new(rhs),
+                step: None,
+                inclusive: op.as_str() == "..=",
+            },
+            Rule::step => match lhs {
+                Ast::Range {
+                    start,
+                    end,
+                    inclusive,
+                    ..
+                } => Ast::Range {
+                    start,
+                    end,
+                    step: Some(Box::new(rhs)),
+                    inclusive,
+                },
+                _ => unreachable!(),
+            },
             _ => unreachable!(),
         })
         .parse(pairs)
 }
-fn handle_block(j: pest::iterators::Pair<'_, Rule>, pratt: &PrattParser<Rule>) -> Vec<Ast> {
+fn handle_block(j: pest::iterators::Pair<'_, Rule>, pratt: &PrattParser<Rule>) -> Vec<Statement> {
     let mut ast = vec![];
     for p in j.into_inner() {
-        match p.as_rule() {
-            Rule::expr => {
-                ast.push(parse_expr(p.into_inner(), &pratt));
-            }
-            Rule::command => {
-                ast.push(handle_command(p, &pratt));
-            }
-            Rule::set => {
-                ast.push(handle_set(p, &pratt));
-            }
-            Rule::ifs => {
-                ast.push(handle_ifs(p, &pratt));
-            }
-            Rule::def => ast.push(handle_def(p, pratt)),
-            Rule::for_loop => ast.push(handle_for_loop(p, pratt)),
+        let line = p.line_col().0;
+        let statement = match p.as_rule() {
+            Rule::expr => parse_expr(p.into_inner(), pratt),
+            Rule::command => handle_command(p, pratt),
+            Rule::set => handle_set(p, pratt),
+            Rule::ifs => handle_ifs(p, pratt),
+            Rule::def => handle_def(p, pratt),
+            Rule::for_loop => handle_for_loop(p, pratt),
+            Rule::comment => Ast::Comment(p.as_str()[2..].trim_end().to_string()),
             _ => {
                 unimplemented!()
             }
-        }
+        };
+        ast.push(Statement {
+            line,
+            ast: statement,
+        });
     }
     ast
 }
-fn parse_code(source: &str) -> Result<Vec<Ast>, pest::error::Error<Rule>> {
+/// A block of the single command `p`, as in `if (x) print(x);`
+fn command_block(p: pest::iterators::Pair<'_, Rule>, pratt: &PrattParser<Rule>) -> Statement {
+    Statement {
+        line: p.line_col().0,
+        ast: handle_command(p, pratt),
+    }
+}
+fn parse_code(source: &str) -> Result<Vec<Statement>, pest::error::Error<Rule>> {
     let mut ast = vec![];
     let pratt = PrattParser::new()
+        .op(Op::infix(Rule::step, Assoc::Left))
+        .op(Op::infix(Rule::range_op, Assoc::Left))
         .op(Op::infix(Rule::comparisonop, Assoc::Left))
         .op(Op::infix(Rule::add, Assoc::Left) | Op::infix(Rule::sub, Assoc::Left))
         .op(Op::infix(Rule::mul, Assoc::Left) | Op::infix(Rule::div, Assoc::Left))
@@ -364,17 +457,17 @@ fn handle_for_loop(p: pest::iterators::Pair<'_, Rule>, pratt: &PrattParser<Rule>
             Rule::ident => element = Some(parse_expr(Pairs::single(i), pratt)),
             Rule::expr => elements = Some(parse_expr(Pairs::single(i), pratt)),
             Rule::block => codeblock = Some(Ast::Block(handle_block(i, pratt))),
-            Rule::command => codeblock = Some(Ast::Block(vec![handle_command(i, pratt)])),
+            Rule::command => codeblock = Some(Ast::Block(vec![command_block(i, pratt)])),
             _ => unreachable!("{:?}", i.as_rule()),
         }
     }
     // Ast::Bool(true)
-    let ast = Ast::ForLoop {
+
+    Ast::ForLoop {
         element: Box::new(element.unwrap()),
         elements: Box::new(elements.unwrap()),
         block: Box::new(codeblock.unwrap()),
-    };
-    ast
+    }
 }
 fn handle_ifs(p: pest::iterators::Pair<'_, Rule>, pratt: &PrattParser<Rule>) -> Ast {
     let mut condition = Ast::Bool(true);
@@ -387,7 +480,7 @@ fn handle_ifs(p: pest::iterators::Pair<'_, Rule>, pratt: &PrattParser<Rule>) ->
             Rule::block => {
                 block.append(&mut handle_block(i, pratt));
             }
-            Rule::command => block.push(handle_command(i, pratt)),
+            Rule::command => block.push(command_block(i, pratt)),
             _ => unreachable!(),
         }
     }
@@ -407,7 +500,7 @@ fn handle_def(p: pest::iterators::Pair<'_, Rule>, pratt: &PrattParser<Rule>) ->
                 for j in i.into_inner() {
                     let mut p_name = String::new();
                     let mut p_type = String::new();
-                    j.into_inner().into_iter().for_each(|f| match f.as_rule() {
+                    j.into_inner().for_each(|f| match f.as_rule() {
                         Rule::ident => p_name = f.as_str().trim().to_string(),
                         Rule::p_type => p_type = f.as_str().to_string(),
                         _ => unreachable!(),
@@ -415,17 +508,17 @@ fn handle_def(p: pest::iterators::Pair<'_, Rule>, pratt: &PrattParser<Rule>) ->
                     params.push((p_name, p_type));
                 }
             }
-            Rule::command => body.push(handle_command(i, pratt)),
+            Rule::command => body.push(command_block(i, pratt)),
             Rule::block => body.append(&mut handle_block(i, pratt)),
             _ => unreachable!(),
         }
     }
-    let returnast = Ast::FunctionDefinition {
+
+    Ast::FunctionDefinition {
         id: ident.to_string(),
         params,
         body: Box::new(Ast::Block(body)),
-    };
-    returnast
+    }
 }
 fn handle_command(p: pest::iterators::Pair<'_, Rule>, pratt: &PrattParser<Rule>) -> Ast {
     let mut fn_identifier = None;
@@ -452,9 +545,17 @@ fn handle_command(p: pest::iterators::Pair<'_, Rule>, pratt: &PrattParser<Rule>)
 fn handle_set(p: pest::iterators::Pair<'_, Rule>, pratt: &PrattParser<Rule>) -> Ast {
     let mut x = None;
     let mut y = None;
+    let mut op = None;
     for i in p.into_inner() {
         match i.as_rule() {
             Rule::ident => x = Some(Ast::Identifier(i.as_str().trim().to_string())),
+            Rule::assign_op => {
+                op = i
+                    .as_str()
+                    .strip_suffix('=')
+                    .filter(|op| !op.is_empty())
+                    .map(str::to_string)
+            }
             Rule::expr => y = Some(parse_expr(i.into_inner(), pratt)),
             Rule::arr => x = Some(handle_arr(i, pratt)),
             _ => unimplemented!("{:?}", i),
@@ -463,6 +564,7 @@ fn handle_set(p: pest::iterators::Pair<'_, Rule>, pratt: &PrattParser<Rule>) ->
     if let (Some(x), Some(y)) = (x, y) {
         Ast::Set {
             id: Box::new(x),
+            op,
             expr: Box::new(y),
         }
     } else {
@@ -491,3 +593,58 @@ impl Ast {
         }
     }
 }
+
+impl Ast {
+    /// Whether this is a call to `return`, after which nothing in its block runs
+    pub fn is_return(&self) -> bool {
+        matches!(self, Ast::FunctionCall { id, .. } if id == "return")
+    }
+
+    /// The same tree with every [`Statement::line`] at 0, to compare code that only differs
+    /// in layout
+    pub fn without_lines(&self) -> Ast {
+        let block = |block: &Ast| Box::new(block.without_lines());
+        match self {
+            Ast::Block(statements) => Ast::Block(
+                statements
+                    .iter()
+                    .map(|s| Statement::from(s.ast.without_lines()))
+                    .collect(),
+            ),
+            Ast::If {
+                condition,
+                block: body,
+            } => Ast::If {
+                condition: condition.clone(),
+                block: block(body),
+            },
+            Ast::FunctionDefinition { id, params, body } => Ast::FunctionDefinition {
+                id: id.clone(),
+                params: params.clone(),
+                body: block(body),
+            },
+            Ast::ForLoop {
+                element,
+                elements,
+                block: body,
+            } => Ast::ForLoop {
+                element: element.clone(),
+                elements: elements.clone(),
+                block: block(body),
+            },
+            other => other.clone(),
+        }
+    }
+}
+
+/// Line and column (both starting at 1) where the code stopped parsing, if `error` comes from
+/// [`Ast::parse_code`]
+pub fn error_location(error: &anyhow::Error) -> Option<(usize, usize)> {
+    let error = error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<pest::error::Error<Rule>>())?;
+    match error.line_col {
+        pest::error::LineColLocation::Pos(position)
+        | pest::error::LineColLocation::Span(position, _) => Some(position),
+    }
+}
@@ -0,0 +1,248 @@
+//! `teo repl`: runs code one input at a time, keeping variables and functions between inputs
+
+use anyhow::Result;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::ops::Range;
+use teolang::program::parser::Ast;
+use teolang::program::{Program, ProgramBuilder, ReturnType};
+use teolang::util::shell::Shell;
+
+const HELP: &str = "\
+Type Teo code and press enter. Code with open braces continues on the next line.
+A bare expression (like `1 + 2`) shows its value.
+
+:vars     show every variable
+:funcs    show every function
+:history  show what you typed so far, numbered
+:history N  run entry N again
+:reset    forget every variable and function
+:help     show this message
+:quit     leave (Ctrl-D works too)";
+
+/// Runs the REPL until stdin ends or `:quit`. `builder` creates the program, again on
+/// `:reset`.
+pub fn run(shell: &mut Shell, builder: impl Fn() -> ProgramBuilder) -> Result<()> {
+    let mut program = builder().build()?;
+    let mut history: Vec<String> = Vec::new();
+    let interactive = io::stdin().is_terminal();
+    if interactive {
+        println!("Teo {}, type :help for help", env!("CARGO_PKG_VERSION"));
+    }
+    while let Some(input) = read_input(interactive)? {
+        let trimmed = input.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        let mut input = trimmed.to_string();
+        if let Some(n) = input.strip_prefix(":history ") {
+            let entry = n
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| n.checked_sub(1).and_then(|i| history.get(i)).cloned());
+            match entry {
+                Some(entry) => {
+                    println!("{}", entry);
+                    input = entry;
+                }
+                None => {
+                    shell.error(format!(
+                        "No entry {} in the history, type :history to see them",
+                        n.trim()
+                    ))?;
+                    continue;
+                }
+            }
+        }
+        // What ran is remembered, not the `:history N` that ran it again
+        history.push(input.clone());
+        match input.as_str() {
+            ":quit" | ":q" => break,
+            ":help" => println!("{}", HELP),
+            ":history" => {
+                for (i, line) in history.iter().enumerate() {
+                    println!("{:>4}  {}", i + 1, line);
+                }
+            }
+            ":vars" => {
+                let mut variables: Vec<_> = program.variable.iter().collect();
+                variables.sort_by(|a, b| a.0.cmp(b.0));
+                for (name, value) in variables {
+                    println!("{} = {}", name, value.as_code());
+                }
+            }
+            ":funcs" => {
+                let mut functions: Vec<_> = program.function.values().collect();
+                functions.sort_by_key(|f| signature(f));
+                for function in functions {
+                    println!("{}", signature(function));
+                }
+                for native in program.natives.names() {
+                    println!("{} (built in)", native);
+                }
+            }
+            ":reset" => {
+                program = builder().build()?;
+                println!("Forgot every variable and function");
+            }
+            command if command.starts_with(':') => {
+                shell.error(format!(
+                    "Unknown command `{}`, type :help for help",
+                    command
+                ))?;
+            }
+            code => {
+                if let Err(e) = evaluate(&mut program, code) {
+                    shell.error(format!("{:#}", e))?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads one input, continuing on the next lines while braces are open. [`None`] once stdin
+/// ends.
+fn read_input(interactive: bool) -> Result<Option<String>> {
+    let mut input = String::new();
+    let stdin = io::stdin();
+    loop {
+        if interactive {
+            print!("{}", if input.is_empty() { "teo> " } else { "...> " });
+            io::stdout().flush()?;
+        }
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return Ok((!input.trim().is_empty()).then_some(input));
+        }
+        input += &line;
+        if open_braces(&input) <= 0 {
+            return Ok(Some(input));
+        }
+    }
+}
+
+/// `{` minus `}` outside of strings and comments
+fn open_braces(code: &str) -> i32 {
+    let code = strip_comments(code);
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut chars = code.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if in_string => {
+                chars.next();
+            }
+            '"' => in_string = !in_string,
+            '{' if !in_string => depth += 1,
+            '}' if !in_string => depth -= 1,
+            _ => {}
+        }
+    }
+    depth
+}
+
+/// Byte ranges of the `//` comments of `code`, leaving out `//` in strings
+fn comments(code: &str) -> Vec<Range<usize>> {
+    let mut comments = Vec::new();
+    let mut in_string = false;
+    let mut chars = code.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' if in_string => {
+                chars.next();
+            }
+            '"' => in_string = !in_string,
+            '/' if !in_string && chars.peek().is_some_and(|&(_, c)| c == '/') => {
+                let end = code[i..].find('\n').map_or(code.len(), |n| i + n);
+                comments.push(i..end);
+                while chars.next_if(|&(j, _)| j < end).is_some() {}
+            }
+            _ => {}
+        }
+    }
+    comments
+}
+
+/// `code` without its comments
+fn strip_comments(code: &str) -> String {
+    let mut stripped = String::new();
+    let mut start = 0;
+    for comment in comments(code) {
+        stripped += &code[start..comment.start];
+        start = comment.end;
+    }
+    stripped + &code[start..]
+}
+
+/// Runs `code` in `program`, showing the value of a bare expression at the end
+fn evaluate(program: &mut Program, code: &str) -> Result<()> {
+    let mut code = code.to_string();
+    // Forgetting the `;` after a single statement is fine here. It goes before a comment
+    // ending the line, if any.
+    let comments = comments(&code);
+    let end = code
+        .char_indices()
+        .rfind(|(i, c)| !c.is_whitespace() && !comments.iter().any(|r| r.contains(i)))
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    if !matches!(code[..end].chars().last(), None | Some(';' | '}')) {
+        code.insert(end, ';');
+    }
+    let mut statements = match Ast::parse_code(&code)? {
+        Ast::Block(statements) => statements,
+        _ => unreachable!(),
+    };
+    // Comments do nothing, and would hide a bare expression before them
+    statements.retain(|statement| !matches!(statement.ast, Ast::Comment(_)));
+    // Defining a function again replaces it
+    for statement in &statements {
+        if let Ast::FunctionDefinition { id, .. } = &statement.ast {
+            program.function.remove(id);
+        }
+    }
+    let show_value = cfg!(feature = "return")
+        && statements
+            .last()
+            .is_some_and(|s| is_bare_expression(&s.ast));
+    if show_value {
+        let last = statements.last_mut().unwrap();
+        last.ast = Ast::FunctionCall {
+            id: "return".to_string(),
+            args: vec![last.ast.clone()],
+        };
+    }
+    program.commands = Ast::Block(statements);
+    if let ReturnType::Ok(value) = program.run()? {
+        if show_value {
+            println!("{}", value.as_code());
+        }
+    }
+    Ok(())
+}
+
+/// Whether `statement` is an expression whose value is worth showing. `print` shows its
+/// arguments already.
+fn is_bare_expression(statement: &Ast) -> bool {
+    match statement {
+        Ast::Set { .. }
+        | Ast::If { .. }
+        | Ast::FunctionDefinition { .. }
+        | Ast::ForLoop { .. }
+        | Ast::Comment(_) => false,
+        Ast::FunctionCall { id, .. } => id != "print" && id != "return",
+        _ => true,
+    }
+}
+
+fn signature(function: &Ast) -> String {
+    match function {
+        Ast::FunctionDefinition { id, params, .. } => {
+            let params: Vec<String> = params
+                .iter()
+                .map(|(name, dtype)| format!("{}: {}", name, dtype))
+                .collect();
+            format!("{}({})", id, params.join(", "))
+        }
+        _ => unreachable!(),
+    }
+}
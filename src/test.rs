@@ -0,0 +1,146 @@
+//! `teo test`: runs every `def test_...` function of a program, each in a program of its own
+//! so no test sees what another one did
+
+use crate::Format;
+use anyhow::Result;
+use serde_json::json;
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+use teolang::program::native::AssertionFailed;
+use teolang::program::parser::{self, Ast, Statement};
+use teolang::program::ProgramBuilder;
+use teolang::util::shell::Shell;
+
+/// Tests run so far
+#[derive(Default)]
+pub struct Results {
+    pub passed: usize,
+    pub failed: usize,
+}
+
+/// How a test went
+struct Outcome {
+    error: Option<anyhow::Error>,
+    /// What the test printed
+    output: String,
+}
+
+/// Runs the tests of `program` (read from `file`) whose name contains `filter`, with programs
+/// from `builder`
+pub fn run(
+    file: &str,
+    program: &Ast,
+    filter: Option<&str>,
+    builder: impl Fn() -> ProgramBuilder,
+    format: Format,
+    shell: &mut Shell,
+    results: &mut Results,
+) -> Result<()> {
+    let statements = match program {
+        Ast::Block(statements) => statements.as_slice(),
+        _ => &[],
+    };
+    // Only the functions are kept, so the top level of the program does not run for every test
+    let definitions: Vec<Statement> = statements
+        .iter()
+        .filter(|s| matches!(s.ast, Ast::FunctionDefinition { .. }))
+        .cloned()
+        .collect();
+    let tests: Vec<&str> = definitions
+        .iter()
+        .filter_map(|definition| match &definition.ast {
+            Ast::FunctionDefinition { id, .. } if id.starts_with("test_") => Some(id.as_str()),
+            _ => None,
+        })
+        .filter(|id| filter.is_none_or(|filter| id.contains(filter)))
+        .collect();
+    if tests.is_empty() && format == Format::Text {
+        shell.warn(format!("No `test_` functions to run in {}", file))?;
+    }
+    for test in tests {
+        let outcome = run_test(test, &definitions, &builder);
+        match format {
+            Format::Text => report(test, file, &outcome, shell)?,
+            Format::Json => {
+                let failed = outcome.error.as_ref().and_then(assertion);
+                shell.print_json(&json!({
+                    "version": parser::JSON_VERSION,
+                    "type": "test",
+                    "file": file,
+                    "name": test,
+                    "passed": outcome.error.is_none(),
+                    "message": outcome.error.as_ref().map(message),
+                    "differences": failed.map_or(&[][..], |f| f.differences.as_slice()),
+                    "output": outcome.output,
+                }))?
+            }
+        }
+        match outcome.error {
+            None => results.passed += 1,
+            Some(_) => results.failed += 1,
+        }
+    }
+    Ok(())
+}
+
+fn run_test(
+    test: &str,
+    definitions: &[Statement],
+    builder: impl Fn() -> ProgramBuilder,
+) -> Outcome {
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let result = builder()
+        .commands(Ast::Block(definitions.to_vec()))
+        .input(Rc::new(RefCell::new(io::empty())))
+        .output(output.clone())
+        .build()
+        .and_then(|mut program| {
+            program.run()?;
+            program.call(test, Vec::new())
+        });
+    let output = String::from_utf8_lossy(&output.borrow()).into_owned();
+    Outcome {
+        error: result.err(),
+        output,
+    }
+}
+
+fn assertion(error: &anyhow::Error) -> Option<&AssertionFailed> {
+    error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<AssertionFailed>())
+}
+
+/// What went wrong: the failed assertion, or the error that stopped the test
+fn message(error: &anyhow::Error) -> String {
+    match assertion(error) {
+        Some(failed) => failed.to_string(),
+        None => error.root_cause().to_string(),
+    }
+}
+
+fn report(test: &str, file: &str, outcome: &Outcome, shell: &mut Shell) -> Result<()> {
+    let name = format!("{} ({})", test, file);
+    let Some(error) = &outcome.error else {
+        return shell.status("Passed", name);
+    };
+    shell.status_with_color("Failed", name, termcolor::Color::Red)?;
+    eprintln!("  {}", message(error));
+    if let Some(failed) = assertion(error) {
+        if let Some((left, right)) = &failed.values {
+            eprintln!("    left:  {}", left);
+            eprintln!("    right: {}", right);
+        }
+        for difference in &failed.differences {
+            eprintln!("    {}", difference);
+        }
+    }
+    if !outcome.output.is_empty() {
+        eprintln!("  It printed:");
+        for line in outcome.output.lines() {
+            eprintln!("    {}", line);
+        }
+    }
+    Ok(())
+}
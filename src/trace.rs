@@ -0,0 +1,142 @@
+//! `teo run --trace`: shows every statement a program runs, the values it computes and the
+//! variables it changes, to see how a program works one step at a time.
+
+use crate::Format;
+use anyhow::Result;
+use serde_json::json;
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+use teolang::program::debug::{Debugger, Pause};
+use teolang::program::parser::{self, Ast};
+use teolang::program::{format, Data};
+use teolang::util::shell::Shell;
+use termcolor::Color;
+
+/// Writes the trace to stderr for people, or to stdout as JSON lines
+pub struct Tracer {
+    shell: Rc<RefCell<Shell>>,
+    format: Format,
+}
+
+impl Tracer {
+    pub fn new(format: Format) -> Tracer {
+        Tracer {
+            shell: Rc::new(RefCell::new(Shell::new())),
+            format,
+        }
+    }
+
+    /// Where the program prints to in `format`: with JSON, its output becomes trace lines
+    /// too, so every line stays JSON
+    pub fn output(&self) -> Option<Rc<RefCell<dyn Write>>> {
+        match self.format {
+            Format::Text => None,
+            Format::Json => Some(Rc::new(RefCell::new(Output {
+                shell: self.shell.clone(),
+                line: Vec::new(),
+            }))),
+        }
+    }
+
+    /// Writes one event: `status` and `message` as text, `event` as JSON
+    fn write(
+        &mut self,
+        at: &Pause<'_>,
+        status: &str,
+        color: Color,
+        message: String,
+        event: serde_json::Value,
+    ) -> Result<()> {
+        let mut shell = self.shell.borrow_mut();
+        match self.format {
+            Format::Text => shell.verbose(|shell| {
+                // Code of functions is indented by how deep they were called
+                let indent = "  ".repeat(at.stack.len());
+                shell.status_with_color(status, format!("{}{}", indent, message), color)
+            }),
+            Format::Json => {
+                let mut event = event;
+                event["version"] = parser::JSON_VERSION.into();
+                event["type"] = "trace".into();
+                event["line"] = at.line.into();
+                event["depth"] = at.stack.len().into();
+                shell.print_json(&event)
+            }
+        }
+    }
+}
+
+impl Debugger for Tracer {
+    fn pause(&mut self, at: &Pause<'_>) -> Result<()> {
+        // Blocks show their first line only, their statements come next
+        let code = format::format(at.statement);
+        let code = code.lines().next().unwrap_or_default().to_string();
+        self.write(
+            at,
+            &format!("line {}", at.line),
+            Color::Cyan,
+            code.clone(),
+            json!({ "event": "statement", "code": code }),
+        )
+    }
+
+    fn evaluated(&mut self, at: &Pause<'_>, expr: &Ast, value: &Data) -> Result<()> {
+        let code = format::expression(expr);
+        // Literals are their own value
+        if code == value.as_code() {
+            return Ok(());
+        }
+        self.write(
+            at,
+            "value",
+            Color::Magenta,
+            format!("{} is {}", code, value.as_code()),
+            json!({ "event": "value", "expression": code, "value": value }),
+        )
+    }
+
+    fn assigned(&mut self, at: &Pause<'_>, name: &str, value: &Data) -> Result<()> {
+        self.write(
+            at,
+            "set",
+            Color::Green,
+            format!("{} = {}", name, value.as_code()),
+            json!({ "event": "set", "variable": name, "value": value }),
+        )
+    }
+}
+
+/// Sends each line the program prints as `{"type": "trace", "event": "output", "text": ...}`
+struct Output {
+    shell: Rc<RefCell<Shell>>,
+    /// What was printed since the last newline
+    line: Vec<u8>,
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            if byte == b'\n' {
+                self.flush()?;
+            } else {
+                self.line.push(byte);
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let text = String::from_utf8_lossy(&self.line).into_owned();
+        self.line.clear();
+        self.shell
+            .borrow_mut()
+            .print_json(&json!({
+                "version": parser::JSON_VERSION,
+                "type": "trace",
+                "event": "output",
+                "text": text,
+            }))
+            .map_err(io::Error::other)
+    }
+}
@@ -442,7 +442,7 @@ impl ShellOut {
         match *self {
             ShellOut::Stream { ref mut stdout, .. } => {
                 stdout.reset()?;
-                stdout.set_color(&color)?;
+                stdout.set_color(color)?;
                 write!(stdout, "{}", fragment)?;
                 stdout.reset()?;
             }
@@ -458,7 +458,7 @@ impl ShellOut {
         match *self {
             ShellOut::Stream { ref mut stderr, .. } => {
                 stderr.reset()?;
-                stderr.set_color(&color)?;
+                stderr.set_color(color)?;
                 write!(stderr, "{}", fragment)?;
                 stderr.reset()?;
             }
@@ -509,7 +509,7 @@ enum Stream {
 }
 
 impl Stream {
-    fn is_terminal(self) -> bool {
+    fn is_terminal(&self) -> bool {
         match self {
             Self::Stdout => std::io::stdout().is_terminal(),
             Self::Stderr => std::io::stderr().is_terminal(),
@@ -522,6 +522,7 @@ mod imp {
     use super::{Shell, TtyWidth};
     use std::mem;
 
+    #[allow(clippy::useless_conversion)]
     pub fn stderr_width() -> TtyWidth {
         unsafe {
             let mut winsize: libc::winsize = mem::zeroed();
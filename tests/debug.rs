@@ -0,0 +1,227 @@
+//! Drives `teo debug` from a terminal and `teo debug --dap` the way an editor would
+
+#![cfg(all(feature = "print", feature = "input", feature = "return"))]
+
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::process::{ChildStdout, Command, Output, Stdio};
+
+/// Debugs `code` with `stdin` and the extra `args` of `teo debug`
+fn debug(code: &str, args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_teo"))
+        .arg("debug")
+        .args(args)
+        .args(["-e", code])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+const GREET: &str = "name = input();\nprint(\"Hi {name}\");\nprint(\"Bye\");";
+
+#[test]
+fn commands_and_input_share_stdin() {
+    // `s` at line 1, `Teo` for `input()`, `p name` and `c` at line 2
+    let output = debug(GREET, &[], "s\nTeo\np name\nc\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Starting at line 1"), "{}", stderr);
+    assert!(stderr.contains("Paused at line 2"), "{}", stderr);
+    assert!(stderr.contains("name = \"Teo\\n\""), "{}", stderr);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "Hi Teo\n\nBye\n");
+    assert!(output.status.success());
+}
+
+#[test]
+fn commands_from_a_file() {
+    let commands = Path::new(env!("CARGO_TARGET_TMPDIR")).join("commands");
+    fs::write(&commands, "b 3\nc\nq\n").unwrap();
+    let output = debug(GREET, &["--commands", commands.to_str().unwrap()], "Teo\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    // Once when set, once when reached
+    let breakpoint = "Breakpoint at line 3: print(\"Bye\");";
+    assert_eq!(stderr.matches(breakpoint).count(), 2, "{}", stderr);
+    // Quitting at line 3 stops before `Bye`, without an error
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "Hi Teo\n\n");
+    assert!(!stderr.contains("error"), "{}", stderr);
+    assert!(output.status.success());
+}
+
+fn send(input: &mut impl Write, message: Value) {
+    let body = message.to_string();
+    write!(input, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+    input.flush().unwrap();
+}
+
+fn receive(output: &mut BufReader<ChildStdout>) -> Value {
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        output.read_line(&mut header).unwrap();
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse().unwrap();
+        }
+    }
+    let mut body = vec![0; length];
+    output.read_exact(&mut body).unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+fn request(seq: u32, command: &str, arguments: Value) -> Value {
+    json!({ "seq": seq, "type": "request", "command": command, "arguments": arguments })
+}
+
+/// Receives the response to the request `seq`, checking that it succeeded
+fn response(output: &mut BufReader<ChildStdout>, seq: u32) -> Value {
+    let response = receive(output);
+    assert_eq!(response["type"], "response", "{}", response);
+    assert_eq!(response["request_seq"], seq, "{}", response);
+    assert_eq!(response["success"], true, "{}", response);
+    response["body"].clone()
+}
+
+fn event(output: &mut BufReader<ChildStdout>, name: &str) -> Value {
+    let event = receive(output);
+    assert_eq!(event["type"], "event", "{}", event);
+    assert_eq!(event["event"], name, "{}", event);
+    event["body"].clone()
+}
+
+#[test]
+fn scripted_dap_session() {
+    let program = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/debug/double.teo");
+    let program = program.to_str().unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_teo"))
+        .args(["debug", "--dap"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut input = child.stdin.take().unwrap();
+    let mut output = BufReader::new(child.stdout.take().unwrap());
+
+    send(
+        &mut input,
+        request(1, "initialize", json!({ "adapterID": "teo" })),
+    );
+    let capabilities = response(&mut output, 1);
+    assert_eq!(capabilities["supportsConfigurationDoneRequest"], true);
+    event(&mut output, "initialized");
+
+    send(
+        &mut input,
+        request(2, "launch", json!({ "program": program })),
+    );
+    response(&mut output, 2);
+    send(
+        &mut input,
+        request(
+            3,
+            "setBreakpoints",
+            json!({ "source": { "path": program }, "breakpoints": [{ "line": 3 }] }),
+        ),
+    );
+    let breakpoints = response(&mut output, 3);
+    assert_eq!(
+        breakpoints["breakpoints"],
+        json!([{ "verified": true, "line": 3 }])
+    );
+    send(&mut input, request(4, "configurationDone", json!({})));
+    response(&mut output, 4);
+    let stopped = event(&mut output, "stopped");
+    assert_eq!(stopped["reason"], "breakpoint");
+
+    send(
+        &mut input,
+        request(5, "stackTrace", json!({ "threadId": 1 })),
+    );
+    let frames = response(&mut output, 5)["stackFrames"].clone();
+    let frames: Vec<(&str, u64)> = frames
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|frame| {
+            (
+                frame["name"].as_str().unwrap(),
+                frame["line"].as_u64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(frames, [("double", 3), ("top level", 5)]);
+
+    send(&mut input, request(6, "scopes", json!({ "frameId": 0 })));
+    let scopes = response(&mut output, 6);
+    let reference = scopes["scopes"][0]["variablesReference"].clone();
+    send(
+        &mut input,
+        request(7, "variables", json!({ "variablesReference": reference })),
+    );
+    let variables = response(&mut output, 7)["variables"].clone();
+    let variables: Vec<(&str, &str)> = variables
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| (v["name"].as_str().unwrap(), v["value"].as_str().unwrap()))
+        .collect();
+    assert_eq!(variables, [("m", "4"), ("n", "2")]);
+
+    send(
+        &mut input,
+        request(8, "evaluate", json!({ "expression": "m" })),
+    );
+    assert_eq!(response(&mut output, 8)["result"], "4");
+    send(
+        &mut input,
+        request(9, "evaluate", json!({ "expression": "x" })),
+    );
+    let failed = receive(&mut output);
+    assert_eq!(failed["request_seq"], 9);
+    assert_eq!(failed["success"], false);
+
+    // `next` leaves `double` for the line after its call
+    send(&mut input, request(10, "next", json!({ "threadId": 1 })));
+    response(&mut output, 10);
+    assert_eq!(event(&mut output, "stopped")["reason"], "step");
+    send(
+        &mut input,
+        request(11, "stackTrace", json!({ "threadId": 1 })),
+    );
+    assert_eq!(response(&mut output, 11)["stackFrames"][0]["line"], 6);
+
+    send(
+        &mut input,
+        request(12, "continue", json!({ "threadId": 1 })),
+    );
+    response(&mut output, 12);
+    // What the program prints comes in pieces, before it exits
+    let mut printed = String::new();
+    let exited = loop {
+        let event = receive(&mut output);
+        match event["event"].as_str() {
+            Some("output") => printed += event["body"]["output"].as_str().unwrap(),
+            _ => break event,
+        }
+    };
+    assert_eq!(printed, "x is 4\n");
+    assert_eq!(exited["event"], "exited");
+    assert_eq!(exited["body"]["exitCode"], 0);
+    event(&mut output, "terminated");
+
+    send(&mut input, request(13, "disconnect", json!({})));
+    response(&mut output, 13);
+    assert!(child.wait().unwrap().success());
+}
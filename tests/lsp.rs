@@ -0,0 +1,180 @@
+//! Talks to `teo lsp` the way an editor would
+
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+const URI: &str = "file:///classroom/main.teo";
+
+fn send(input: &mut impl Write, message: Value) {
+    let body = message.to_string();
+    write!(input, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+    input.flush().unwrap();
+}
+
+fn receive(output: &mut BufReader<ChildStdout>) -> Value {
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        output.read_line(&mut header).unwrap();
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse().unwrap();
+        }
+    }
+    let mut body = vec![0; length];
+    output.read_exact(&mut body).unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+fn request(id: u32, method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+fn at(line: u32, character: u32) -> Value {
+    json!({
+        "textDocument": { "uri": URI },
+        "position": { "line": line, "character": character },
+    })
+}
+
+/// Starts `teo lsp`, returning it with its stdin and stdout
+fn spawn() -> (Child, ChildStdin, BufReader<ChildStdout>) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_teo"))
+        .arg("lsp")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let input = child.stdin.take().unwrap();
+    let output = BufReader::new(child.stdout.take().unwrap());
+    (child, input, output)
+}
+
+fn open(input: &mut ChildStdin, code: &str) {
+    send(
+        input,
+        notification(
+            "textDocument/didOpen",
+            json!({ "textDocument": { "uri": URI, "languageId": "teo", "version": 1, "text": code } }),
+        ),
+    );
+}
+
+#[test]
+fn scripted_session() {
+    let (mut child, mut input, mut output) = spawn();
+
+    send(&mut input, request(1, "initialize", json!({})));
+    let initialized = receive(&mut output);
+    assert_eq!(initialized["id"], 1);
+    assert_eq!(initialized["result"]["capabilities"]["hoverProvider"], true);
+
+    let code = "def double(n: Number) {\n  return(n*2);\n}\nx = double(y);\ndouble(x);\n";
+    open(&mut input, code);
+    let published = receive(&mut output);
+    assert_eq!(published["method"], "textDocument/publishDiagnostics");
+    let diagnostics = published["params"]["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0]["severity"], 1);
+    assert_eq!(diagnostics[0]["range"]["start"]["line"], 3);
+    assert_eq!(diagnostics[0]["message"], "Variable `y` is not defined");
+
+    send(&mut input, request(2, "textDocument/completion", at(4, 0)));
+    let completion = receive(&mut output);
+    let labels: Vec<&str> = completion["result"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["label"].as_str().unwrap())
+        .collect();
+    // Builtins depend on the enabled features, so only names from the code
+    for label in ["double", "x", "n", "for"] {
+        assert!(labels.contains(&label), "no completion for {}", label);
+    }
+
+    send(&mut input, request(3, "textDocument/hover", at(3, 6)));
+    let hover = receive(&mut output);
+    assert_eq!(
+        hover["result"]["contents"]["value"],
+        "```teo\ndef double(n: Number)\n```"
+    );
+
+    send(&mut input, request(4, "textDocument/definition", at(3, 6)));
+    let definition = receive(&mut output);
+    assert_eq!(definition["result"]["uri"], URI);
+    assert_eq!(
+        definition["result"]["range"]["start"],
+        json!({ "line": 0, "character": 4 })
+    );
+
+    send(
+        &mut input,
+        request(
+            5,
+            "textDocument/formatting",
+            json!({ "textDocument": { "uri": URI }, "options": { "tabSize": 4, "insertSpaces": true } }),
+        ),
+    );
+    let formatting = receive(&mut output);
+    assert_eq!(
+        formatting["result"][0]["newText"],
+        "def double(n: Number) {\n    return(n * 2);\n}\n\nx = double(y);\ndouble(x);\n"
+    );
+
+    // A parse error points at where the code stops making sense
+    send(
+        &mut input,
+        notification(
+            "textDocument/didChange",
+            json!({
+                "textDocument": { "uri": URI, "version": 2 },
+                "contentChanges": [{ "text": "x = 1;\ny = (2 + ;\n" }],
+            }),
+        ),
+    );
+    let published = receive(&mut output);
+    let diagnostics = published["params"]["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0]["range"]["start"]["line"], 1);
+
+    send(&mut input, request(6, "teo/unknown", json!({})));
+    assert_eq!(receive(&mut output)["error"]["code"], -32601);
+
+    send(&mut input, request(7, "shutdown", Value::Null));
+    assert_eq!(receive(&mut output)["id"], 7);
+    send(&mut input, notification("exit", Value::Null));
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn definition_of_a_short_name() {
+    let (mut child, mut input, mut output) = spawn();
+    send(&mut input, request(1, "initialize", json!({})));
+    receive(&mut output);
+    // `f` is also in `def`, and `df` starts with it
+    open(
+        &mut input,
+        "df = 1;\ndef  f(n: Number) {\n  return(n + df);\n}\nx = f(1);\n",
+    );
+    receive(&mut output);
+
+    send(&mut input, request(2, "textDocument/definition", at(4, 4)));
+    let definition = receive(&mut output);
+    assert_eq!(
+        definition["result"]["range"],
+        json!({ "start": { "line": 1, "character": 5 }, "end": { "line": 1, "character": 6 } })
+    );
+
+    send(&mut input, request(3, "shutdown", Value::Null));
+    receive(&mut output);
+    send(&mut input, notification("exit", Value::Null));
+    assert!(child.wait().unwrap().success());
+}
@@ -0,0 +1,153 @@
+//! Runs every `.teo` file of `tests/programs` and compares what it does with the files next to
+//! it, named after it:
+//!
+//! - `NAME.stdin`: what the program reads (nothing if missing)
+//! - `NAME.stdout`: what it prints
+//! - `NAME.stderr`: its errors, without the `Running` status line
+//! - `NAME.exit`: the code it exits with (0 if missing)
+//! - `NAME.json`: its syntax tree as `teo --format json ast` prints it (pretty), which pins
+//!   the JSON schema and its version (only checked if the file exists)
+//!
+//! Missing files expect nothing printed. Each program runs with both engines. To write the
+//! expected files from what the programs do now, run `TEO_BLESS=1 cargo test --test programs`
+//! and review the changes. The programs need the default builtins, so nothing runs without them.
+
+#![cfg(all(
+    feature = "print",
+    feature = "return",
+    feature = "split",
+    feature = "inputf",
+    feature = "range",
+    feature = "assert"
+))]
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// What a program did
+#[derive(Debug, PartialEq, Eq)]
+struct Run {
+    stdout: String,
+    stderr: String,
+    exit: i32,
+}
+
+fn run(program: &Path, engine: &str) -> Run {
+    let stdin = fs::read(program.with_extension("stdin")).unwrap_or_default();
+    // From the program's directory, so errors name the file the same way on every machine
+    let mut child = Command::new(env!("CARGO_BIN_EXE_teo"))
+        .current_dir(program.parent().unwrap())
+        .args(["--engine", engine, "run"])
+        .arg(program.file_name().unwrap())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // The program may stop before reading everything
+    let _ = child.stdin.take().unwrap().write_all(&stdin);
+    let output = child.wait_with_output().unwrap();
+    let stderr: Vec<&str> = std::str::from_utf8(&output.stderr)
+        .unwrap()
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("Running "))
+        .collect();
+    Run {
+        stdout: String::from_utf8(output.stdout).unwrap(),
+        stderr: stderr.iter().map(|line| format!("{}\n", line)).collect(),
+        exit: output.status.code().unwrap(),
+    }
+}
+
+/// Syntax tree of `program` in JSON, pretty so its changes are easy to review
+fn ast(program: &Path) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_teo"))
+        .current_dir(program.parent().unwrap())
+        .args(["--format", "json", "ast"])
+        .arg(program.file_name().unwrap())
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    format!("{}\n", serde_json::to_string_pretty(&json).unwrap())
+}
+
+fn expected(program: &Path) -> Run {
+    let read =
+        |extension| fs::read_to_string(program.with_extension(extension)).unwrap_or_default();
+    Run {
+        stdout: read("stdout"),
+        stderr: read("stderr"),
+        exit: read("exit").trim().parse().unwrap_or(0),
+    }
+}
+
+/// Writes `contents` to the file of `program` with `extension`, or removes it if empty
+fn bless(program: &Path, extension: &str, contents: &str) {
+    let path = program.with_extension(extension);
+    if contents.is_empty() {
+        let _ = fs::remove_file(path);
+    } else {
+        fs::write(path, contents).unwrap();
+    }
+}
+
+#[test]
+fn programs() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs");
+    let mut programs: Vec<PathBuf> = fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "teo"))
+        .collect();
+    programs.sort();
+    assert!(!programs.is_empty(), "no programs to run");
+    let blessing = std::env::var_os("TEO_BLESS").is_some_and(|v| v != "0");
+    let mut failures = Vec::new();
+    for program in &programs {
+        if blessing {
+            let actual = run(program, "bytecode");
+            bless(program, "stdout", &actual.stdout);
+            bless(program, "stderr", &actual.stderr);
+            let exit = match actual.exit {
+                0 => String::new(),
+                code => format!("{}\n", code),
+            };
+            bless(program, "exit", &exit);
+            if program.with_extension("json").exists() {
+                bless(program, "json", &ast(program));
+            }
+        }
+        if let Ok(expected) = fs::read_to_string(program.with_extension("json")) {
+            let actual = ast(program);
+            if actual != expected {
+                failures.push(format!(
+                    "{} (ast)\nexpected: {}\nactual: {}",
+                    program.display(),
+                    expected,
+                    actual
+                ));
+            }
+        }
+        let expected = expected(program);
+        for engine in ["bytecode", "tree"] {
+            let actual = run(program, engine);
+            if actual != expected {
+                failures.push(format!(
+                    "{} (--engine {})\nexpected: {:#?}\nactual: {:#?}",
+                    program.display(),
+                    engine,
+                    expected,
+                    actual
+                ));
+            }
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{} runs or syntax trees differ from what was expected (TEO_BLESS=1 updates them):\n\n{}",
+        failures.len(),
+        failures.join("\n\n")
+    );
+}
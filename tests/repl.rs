@@ -0,0 +1,42 @@
+//! Types into `teo repl` the way a user would
+
+#![cfg(all(feature = "print", feature = "return"))]
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+/// Runs `teo repl` with `stdin` typed in
+fn repl(stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_teo"))
+        .arg("repl")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn history_runs_an_entry_again() {
+    let output = repl("x = 1\nx = x * 3\nx\n:history 2\n:history 3\n:history\n:history 9\n");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    // Entry 3 of the history is `x`, and running `:history 2` again remembered `x = x * 3`
+    assert_eq!(
+        stdout,
+        "3\nx = x * 3\nx\n9\n   1  x = 1\n   2  x = x * 3\n   3  x\n   4  x = x * 3\n   5  x\n   6  :history\n"
+    );
+    assert!(
+        stderr.contains("No entry 9 in the history, type :history to see them"),
+        "{}",
+        stderr
+    );
+    assert!(output.status.success());
+}